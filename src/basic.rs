@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/basic.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use std::collections::HashSet;
+
+use crate::D64Error;
+
+const FIRST_TOKEN: u8 = 0x80;
+const DATA_TOKEN: u8 = 0x83;
+const REM_TOKEN: u8 = 0x8F;
+const PI_TOKEN: u8 = 0xFF;
+const TOKENS: [&str; 76] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT#", "INPUT", "DIM", "READ", "LET", "GOTO", "RUN", "IF",
+    "RESTORE", "GOSUB", "RETURN", "REM", "STOP", "ON", "WAIT", "LOAD", "SAVE", "VERIFY", "DEF",
+    "POKE", "PRINT#", "PRINT", "CONT", "LIST", "CLR", "CMD", "SYS", "OPEN", "CLOSE", "GET", "NEW",
+    "TAB(", "TO", "FN", "SPC(", "THEN", "NOT", "STEP", "+", "-", "*", "/", "^", "AND", "OR", ">",
+    "=", "<", "SGN", "INT", "ABS", "USR", "FRE", "POS", "SQR", "RND", "LOG", "EXP", "COS", "SIN",
+    "TAN", "ATN", "PEEK", "LEN", "STR$", "VAL", "ASC", "CHR$", "LEFT$", "RIGHT$", "MID$", "GO",
+];
+
+pub fn detokenize(load_addr: u16, body: &[u8]) -> Result<String, D64Error> {
+    let mut listing = String::new();
+    let mut offset = 0;
+    let mut visited = HashSet::new();
+
+    loop {
+        if offset + 2 > body.len() {
+            return Err(D64Error::InvalidBasic);
+        }
+        let link = u16::from_le_bytes([body[offset], body[offset + 1]]);
+        if link == 0 {
+            break;
+        }
+        if !visited.insert(offset) || offset + 4 > body.len() {
+            return Err(D64Error::InvalidBasic);
+        }
+
+        let line_number = u16::from_le_bytes([body[offset + 2], body[offset + 3]]);
+        let text = &body[offset + 4..];
+        let end = text
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(D64Error::InvalidBasic)?;
+
+        listing.push_str(&format!("{} ", line_number));
+        let mut in_quotes = false;
+        for &byte in &text[..end] {
+            match byte {
+                b'"' => {
+                    in_quotes = !in_quotes;
+                    listing.push('"');
+                }
+                FIRST_TOKEN..=0xCB if !in_quotes => {
+                    listing.push_str(TOKENS[(byte - FIRST_TOKEN) as usize]);
+                }
+                PI_TOKEN if !in_quotes => listing.push_str("{PI}"),
+                0x20..=0x5F => listing.push(byte as char),
+                0xC1..=0xDA => listing.push((byte - 0x80) as char),
+                _ => listing.push_str(&format!("{{${:02X}}}", byte)),
+            }
+        }
+        listing.push('\n');
+
+        offset = link.wrapping_sub(load_addr) as usize;
+    }
+
+    Ok(listing)
+}
+
+pub fn tokenize(source: &str, load_addr: u16) -> Result<Vec<u8>, D64Error> {
+    let mut program = Vec::new();
+
+    for line in source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let line_number: u16 = line[..digits]
+            .parse()
+            .ok()
+            .filter(|&number| number <= 63999)
+            .ok_or(D64Error::InvalidBasic)?;
+
+        let mut encoded = line_number.to_le_bytes().to_vec();
+        encoded.extend(tokenize_statement(line[digits..].trim_start())?);
+        encoded.push(0);
+
+        let next_line = load_addr as usize + program.len() + 2 + encoded.len();
+        let link = u16::try_from(next_line).map_err(|_| D64Error::InvalidBasic)?;
+        program.extend_from_slice(&link.to_le_bytes());
+        program.extend(encoded);
+    }
+
+    program.extend_from_slice(&[0, 0]);
+    Ok(program)
+}
+
+fn tokenize_statement(text: &str) -> Result<Vec<u8>, D64Error> {
+    let text = text.as_bytes();
+    let mut bytes = Vec::new();
+    let mut in_quotes = false;
+    let mut in_data = false;
+    let mut in_rem = false;
+    let mut pos = 0;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if let Some((byte, len)) = parse_escape(rest, in_quotes) {
+            bytes.push(byte);
+            pos += len;
+            continue;
+        }
+
+        let c = rest[0].to_ascii_uppercase();
+        if !in_quotes && !in_data && !in_rem {
+            // Longest match first, so GOTO wins over GO and INPUT# over INPUT.
+            let keyword = TOKENS
+                .iter()
+                .enumerate()
+                .filter(|(_, keyword)| rest.len() >= keyword.len())
+                .filter(|(_, keyword)| {
+                    rest[..keyword.len()].eq_ignore_ascii_case(keyword.as_bytes())
+                })
+                .max_by_key(|(_, keyword)| keyword.len());
+            if let Some((index, keyword)) = keyword {
+                let token = FIRST_TOKEN + index as u8;
+                bytes.push(token);
+                pos += keyword.len();
+                // The rest of a REM line is kept as text, escapes included.
+                in_rem = token == REM_TOKEN;
+                in_data = token == DATA_TOKEN;
+                continue;
+            }
+        }
+
+        match c {
+            b'"' => in_quotes = !in_quotes,
+            b':' if !in_quotes => in_data = false,
+            0x20..=0x5F => {}
+            _ => return Err(D64Error::InvalidBasic),
+        }
+        bytes.push(c);
+        pos += 1;
+    }
+
+    Ok(bytes)
+}
+
+fn parse_escape(text: &[u8], in_quotes: bool) -> Option<(u8, usize)> {
+    if text.starts_with(b"{PI}") && !in_quotes {
+        return Some((PI_TOKEN, 4));
+    }
+    if text.len() >= 5 && text.starts_with(b"{$") && text[4] == b'}' {
+        let hex = std::str::from_utf8(&text[2..4]).ok()?;
+        return u8::from_str_radix(hex, 16).ok().map(|byte| (byte, 5));
+    }
+    None
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/gcr.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use crate::D64Error;
+
+pub const GCR_ENCODE: [u8; 16] = [
+    0x0A, 0x0B, 0x12, 0x13, 0x0E, 0x0F, 0x16, 0x17, 0x09, 0x19, 0x1A, 0x1B, 0x0D, 0x1D, 0x1E, 0x15,
+];
+
+pub const GCR_DECODE: [Option<u8>; 32] = gcr_decode_table();
+
+const fn gcr_decode_table() -> [Option<u8>; 32] {
+    let mut table = [None; 32];
+    let mut nibble = 0;
+    while nibble < 16 {
+        table[GCR_ENCODE[nibble] as usize] = Some(nibble as u8);
+        nibble += 1;
+    }
+    table
+}
+
+// Every 4 data bytes become 5 GCR bytes; a trailing partial group is padded
+// with zero bytes.
+pub fn encode_gcr(data: &[u8]) -> Vec<u8> {
+    let mut gcr = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bits = 0u64;
+        for i in 0..4 {
+            let byte = group.get(i).copied().unwrap_or(0);
+            bits = (bits << 10)
+                | ((GCR_ENCODE[(byte >> 4) as usize] as u64) << 5)
+                | GCR_ENCODE[(byte & 0x0F) as usize] as u64;
+        }
+        gcr.extend_from_slice(&bits.to_be_bytes()[3..]);
+    }
+    gcr
+}
+
+pub fn decode_gcr(gcr: &[u8]) -> Result<Vec<u8>, D64Error> {
+    if !gcr.len().is_multiple_of(5) {
+        return Err(D64Error::InvalidGcr);
+    }
+
+    let mut data = Vec::with_capacity(gcr.len() / 5 * 4);
+    for group in gcr.chunks(5) {
+        let bits = group
+            .iter()
+            .fold(0u64, |bits, &byte| (bits << 8) | byte as u64);
+        for i in (0..8).step_by(2) {
+            let high = decode_quintet(bits >> (35 - i * 5))?;
+            let low = decode_quintet(bits >> (30 - i * 5))?;
+            data.push((high << 4) | low);
+        }
+    }
+    Ok(data)
+}
+
+fn decode_quintet(bits: u64) -> Result<u8, D64Error> {
+    GCR_DECODE[(bits & 0x1F) as usize].ok_or(D64Error::InvalidGcr)
+}
@@ -4,21 +4,122 @@
 // Author: Volker Schwaberow <volker@schwaberow.de>
 // Copyright (c) 2024 Volker Schwaberow
 
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+pub mod basic;
+pub mod gcr;
+pub mod lynx;
+pub mod pc64;
+pub mod rel;
+#[cfg(feature = "serde")]
+mod serde_support;
+pub mod t64;
+
 #[cfg(test)]
 mod tests;
 
 const D64_35_TRACKS_SIZE: usize = 174848;
 const D64_40_TRACKS_SIZE: usize = 196608;
-const MAX_TRACKS: u8 = 40;
-const SECTORS_PER_TRACK: [u8; 40] = [
+const D64_35_TRACKS_ERRORS_SIZE: usize = 175531;
+const D64_40_TRACKS_ERRORS_SIZE: usize = 197376;
+const D64_41_TRACKS_SIZE: usize = 200960;
+const D64_41_TRACKS_ERRORS_SIZE: usize = 201745;
+const D64_42_TRACKS_SIZE: usize = 205312;
+const D64_42_TRACKS_ERRORS_SIZE: usize = 206114;
+const D71_70_TRACKS_SIZE: usize = 349696;
+const D71_TRACKS: u8 = 70;
+const D81_80_TRACKS_SIZE: usize = 819200;
+const D81_TRACKS: u8 = 80;
+const D81_SECTORS_PER_TRACK: u8 = 40;
+const IMAGE_SIZES: [usize; 10] = [
+    D64_35_TRACKS_SIZE,
+    D64_35_TRACKS_ERRORS_SIZE,
+    D64_40_TRACKS_SIZE,
+    D64_40_TRACKS_ERRORS_SIZE,
+    D64_41_TRACKS_SIZE,
+    D64_41_TRACKS_ERRORS_SIZE,
+    D64_42_TRACKS_SIZE,
+    D64_42_TRACKS_ERRORS_SIZE,
+    D71_70_TRACKS_SIZE,
+    D81_80_TRACKS_SIZE,
+];
+const MAX_TRACKS: u8 = 80;
+const DEFAULT_INTERLEAVE: u8 = 10;
+const DATA_CHECKSUM_ERROR: u8 = 0x05;
+const SECTORS_PER_TRACK: [u8; 42] = [
     21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 19, 19, 19, 19, 19, 19, 19,
-    18, 18, 18, 18, 18, 18, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17,
+    18, 18, 18, 18, 18, 18, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17,
+];
+
+const TRACK_OFFSETS: [usize; 43] = track_offsets();
+const PETSCII_UPPERCASE_HIGH: [char; 64] = [
+    '\u{a0}', '▌', '▄', '▔', '▁', '▏', '▒', '▕', '🮏', '◤', '🮇', '├', '▗', '└', '┐', '▂', '┌', '┴',
+    '┬', '┤', '▎', '▍', '🮈', '🮂', '🮃', '▃', '🭿', '▖', '▝', '┘', '▘', '▚', '─', '♠', '🭲', '🭸', '🭷',
+    '🭶', '🭺', '🭱', '🭴', '╮', '╰', '╯', '🭼', '╲', '╱', '🭽', '🭾', '●', '🭻', '♥', '🭰', '╭', '╳', '○',
+    '♣', '🭵', '♦', '┼', '🮌', '│', 'π', '◥',
 ];
 
+const PETSCII_LOWERCASE_HIGH: [char; 64] = [
+    '\u{a0}', '▌', '▄', '▔', '▁', '▏', '▒', '▕', '🮏', '🮙', '🮇', '├', '▗', '└', '┐', '▂', '┌', '┴',
+    '┬', '┤', '▎', '▍', '🮈', '🮂', '🮃', '▃', '✓', '▖', '▝', '┘', '▘', '▚', '─', 'A', 'B', 'C', 'D',
+    'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W',
+    'X', 'Y', 'Z', '┼', '🮌', '│', '🮖', '🮘',
+];
+
+const fn track_offsets() -> [usize; 43] {
+    let mut offsets = [0; 43];
+    let mut track = 0;
+    while track < SECTORS_PER_TRACK.len() {
+        offsets[track + 1] = offsets[track] + SECTORS_PER_TRACK[track] as usize * 256;
+        track += 1;
+    }
+    offsets
+}
+
+fn sectors_per_track(tracks: u8, track: u8) -> Option<u8> {
+    if track == 0 || track > tracks {
+        return None;
+    }
+    if tracks == D81_TRACKS {
+        return Some(D81_SECTORS_PER_TRACK);
+    }
+    let track_idx = if tracks == D71_TRACKS && track > 35 {
+        track - 36
+    } else {
+        track - 1
+    };
+    Some(SECTORS_PER_TRACK[track_idx as usize])
+}
+
+fn dir_track_for(tracks: u8) -> u8 {
+    if tracks == D81_TRACKS {
+        40
+    } else {
+        18
+    }
+}
+
+fn allocation_order(tracks: u8, dir_track: u8, allow_dir_track: bool) -> Vec<u8> {
+    let mut order = Vec::with_capacity(tracks as usize);
+    for distance in 1..tracks {
+        if distance < dir_track {
+            order.push(dir_track - distance);
+        }
+        if dir_track + distance <= tracks {
+            order.push(dir_track + distance);
+        }
+    }
+    if allow_dir_track {
+        order.push(dir_track);
+    }
+    order
+}
+
 #[derive(Error, Debug)]
 pub enum D64Error {
     #[error("IO error: {0}")]
@@ -31,26 +132,427 @@ pub enum D64Error {
     FileNotFound,
     #[error("Disk full")]
     DiskFull,
+    #[error("File name too long")]
+    NameTooLong,
+    #[error("Invalid archive format")]
+    InvalidArchive,
+    #[error("File too short")]
+    FileTooShort,
+    #[error("Sector already allocated")]
+    SectorInUse,
+    #[error("Sector data must be 256 bytes, got {0}")]
+    InvalidSectorLength(usize),
+    #[error("Not a REL file")]
+    NotRelFile,
+    #[error("Invalid BASIC program")]
+    InvalidBasic,
+    #[error("Track data has the wrong length: {0} bytes")]
+    InvalidTrackLength(usize),
+    #[error("Image is read-only")]
+    ReadOnly,
+    #[error("Invalid GCR data")]
+    InvalidGcr,
+    #[error("Character {0:?} cannot be encoded in PETSCII")]
+    UnrepresentableChar(char),
+    #[error("REL and GEOS files cannot be relocated")]
+    CannotRelocate,
+    #[error("Saved image does not match the image in memory")]
+    VerifyFailed,
+    #[error("File is locked")]
+    FileLocked,
+    #[error("{0} files cannot be inserted")]
+    UnsupportedFileType(FileType),
+}
+
+impl D64Error {
+    // Closest 1541 error channel number and message for each error, as a
+    // drive would report it in "62,FILE NOT FOUND,00,00".
+    pub fn to_cbm_error(&self) -> (u8, String) {
+        let (code, message) = match self {
+            D64Error::Io(_) | D64Error::InvalidFileSize => (74, "DRIVE NOT READY"),
+            D64Error::InvalidTrackSector => (66, "ILLEGAL TRACK AND SECTOR"),
+            D64Error::FileNotFound => (62, "FILE NOT FOUND"),
+            D64Error::DiskFull => (72, "DISK FULL"),
+            D64Error::NameTooLong | D64Error::UnrepresentableChar(_) => (33, "SYNTAX ERROR"),
+            D64Error::InvalidSectorLength(_) | D64Error::InvalidTrackLength(_) => {
+                (30, "SYNTAX ERROR")
+            }
+            D64Error::InvalidArchive
+            | D64Error::NotRelFile
+            | D64Error::InvalidBasic
+            | D64Error::CannotRelocate
+            | D64Error::UnsupportedFileType(_) => (64, "FILE TYPE MISMATCH"),
+            D64Error::FileTooShort => (50, "RECORD NOT PRESENT"),
+            D64Error::SectorInUse => (65, "NO BLOCK"),
+            D64Error::ReadOnly | D64Error::FileLocked => (26, "WRITE PROTECT ON"),
+            D64Error::InvalidGcr => (24, "READ ERROR"),
+            D64Error::VerifyFailed => (25, "WRITE ERROR"),
+        };
+        (code, message.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BamLayout {
+    #[default]
+    SpeedDos,
+    DolphinDos,
+}
+
+impl BamLayout {
+    fn entry_offset(self, track_idx: usize) -> usize {
+        match (track_idx, self) {
+            (0..=34, _) => 4 + track_idx * 4,
+            (_, BamLayout::SpeedDos) => 0xC0 + (track_idx - 35) * 4,
+            (_, BamLayout::DolphinDos) => 0xAC + (track_idx - 35) * 4,
+        }
+    }
+
+    fn detect(data: &[u8], tracks: u8) -> Self {
+        let speed_dos_empty = data[0xC0..0xD4].iter().all(|&byte| byte == 0);
+        let dolphin_dos_used = data[0xAC..0xC0].iter().any(|&byte| byte != 0);
+        if tracks == 40 && speed_dos_empty && dolphin_dos_used {
+            BamLayout::DolphinDos
+        } else {
+            BamLayout::SpeedDos
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterleavePolicy {
+    #[default]
+    Standard,
+    Contiguous,
+    Custom(u8),
+}
+
+impl InterleavePolicy {
+    fn interleave(self) -> u8 {
+        match self {
+            InterleavePolicy::Standard => DEFAULT_INTERLEAVE,
+            InterleavePolicy::Contiguous => 1,
+            InterleavePolicy::Custom(interleave) => interleave,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CharSet {
+    #[default]
+    Uppercase,
+    Lowercase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileType {
+    Del,
+    Seq,
+    Prg,
+    Usr,
+    Rel,
+}
+
+impl FileType {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte & 0x07 {
+            0 => Some(FileType::Del),
+            1 => Some(FileType::Seq),
+            2 => Some(FileType::Prg),
+            3 => Some(FileType::Usr),
+            4 => Some(FileType::Rel),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            FileType::Del => 0,
+            FileType::Seq => 1,
+            FileType::Prg => 2,
+            FileType::Usr => 3,
+            FileType::Rel => 4,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileType::Del => "DEL",
+            FileType::Seq => "SEQ",
+            FileType::Prg => "PRG",
+            FileType::Usr => "USR",
+            FileType::Rel => "REL",
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirEntry {
+    pub name: String,
+    pub name_bytes: [u8; 16],
+    pub file_type: FileType,
+    pub size_blocks: u16,
+    pub start_track: u8,
+    pub start_sector: u8,
+    pub is_closed: bool,
+    pub locked: bool,
+    pub geos: Option<GeosInfo>,
+    pub record_length: Option<u8>,
+    pub side_sector_track: Option<u8>,
+    pub side_sector_sector: Option<u8>,
+}
+
+pub type DirSlot = (u8, u8, usize, [u8; 32]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GeosInfo {
+    pub info_track: u8,
+    pub info_sector: u8,
+    pub geos_type: u8,
+    pub vlir: bool,
+}
+
+impl DirEntry {
+    // The name with control and graphics codes escaped, safe for a terminal.
+    pub fn display_name(&self) -> String {
+        let name_end = self
+            .name_bytes
+            .iter()
+            .position(|&x| x == 0xA0)
+            .unwrap_or(16);
+        petscii_to_display(&self.name_bytes[..name_end])
+    }
+
+    fn from_slot(slot: &[u8]) -> Option<Self> {
+        let type_byte = slot[2];
+        if type_byte == 0 || type_byte & 0x07 == 0 {
+            return None;
+        }
+        let file_type = FileType::from_byte(type_byte)?;
+        let geos = (file_type != FileType::Rel && slot[24] != 0).then(|| GeosInfo {
+            info_track: slot[21],
+            info_sector: slot[22],
+            geos_type: slot[24],
+            vlir: slot[23] == 1,
+        });
+        let rel = file_type == FileType::Rel;
+        let mut name_bytes = [0u8; 16];
+        name_bytes.copy_from_slice(&slot[5..21]);
+        Some(DirEntry {
+            name: entry_name(&name_bytes),
+            name_bytes,
+            file_type,
+            size_blocks: u16::from_le_bytes([slot[30], slot[31]]),
+            start_track: slot[3],
+            start_sector: slot[4],
+            is_closed: type_byte & 0x80 != 0,
+            locked: type_byte & 0x40 != 0,
+            geos,
+            record_length: rel.then_some(slot[23]),
+            side_sector_track: rel.then_some(slot[21]),
+            side_sector_sector: rel.then_some(slot[22]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discrepancy {
+    AllocatedButUnused,
+    UsedButFree,
+    CrossLinked,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub discrepancies: Vec<(u8, u8, Discrepancy)>,
+}
+
+struct DirEntries<'a> {
+    d64: &'a D64,
+    sector: u8,
+    slot: usize,
+    visited_sectors: HashSet<u8>,
+    done: bool,
+}
+
+impl Iterator for DirEntries<'_> {
+    type Item = Result<DirEntry, D64Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dir_track = self.d64.dir_track;
+        while !self.done {
+            let data = match self.d64.read_sector(dir_track, self.sector) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+
+            while self.slot < 8 {
+                let i = self.slot * 32;
+                self.slot += 1;
+                if let Some(entry) = DirEntry::from_slot(&data[i..i + 32]) {
+                    return Some(Ok(entry));
+                }
+            }
+
+            let next_track = data[0];
+            let next_sector = data[1];
+            if next_track == 0
+                || (next_track == dir_track && next_sector == self.d64.first_dir_sector())
+            {
+                self.done = true;
+                return None;
+            }
+
+            if next_track != dir_track
+                || next_sector >= sectors_per_track(self.d64.tracks, dir_track).unwrap_or(0)
+                || !self.visited_sectors.insert(next_sector)
+            {
+                self.done = true;
+                return Some(Err(D64Error::InvalidTrackSector));
+            }
+
+            self.sector = next_sector;
+            self.slot = 0;
+        }
+        None
+    }
 }
 
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    fn push(&mut self, track: u8, sector: u8, kind: Discrepancy) {
+        self.discrepancies.push((track, sector, kind));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileUsage {
+    pub name: String,
+    pub blocks: u16,
+    pub stored_blocks: u16,
+}
+
+impl FileUsage {
+    pub fn size_mismatch(&self) -> bool {
+        self.blocks != self.stored_blocks
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScratchedEntry {
+    pub name: String,
+    pub start_track: u8,
+    pub start_sector: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorSpan {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootInfo {
+    pub c128_boot: bool,
+    pub message: Option<String>,
+    pub likely_autostart: bool,
+    pub first_bytes: Vec<u8>,
+}
+
+#[derive(Clone)]
 pub struct D64 {
     pub data: Vec<u8>,
     pub tracks: u8,
+    pub error_info: Option<Vec<u8>>,
+    pub dir_track: u8,
+    read_only: bool,
 }
 
+impl PartialEq for D64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.tracks == other.tracks
+            && self.dir_track == other.dir_track
+            && self.data == other.data
+            && self.error_info == other.error_info
+    }
+}
+
+impl Eq for D64 {}
+
+impl fmt::Debug for D64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("D64")
+            .field("tracks", &self.tracks)
+            .field("dir_track", &self.dir_track)
+            .field("size", &self.data.len())
+            .field(
+                "content_hash",
+                &format_args!("{:016x}", self.content_hash()),
+            )
+            .field("error_info", &self.error_info.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BAM {
     pub tracks: u8,
-    pub free_sectors: [u8; 40],
-    pub bitmap: [[u8; 3]; 40],
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::track_counts"))]
+    pub free_sectors: [u8; MAX_TRACKS as usize],
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::track_bitmaps"))]
+    pub bitmap: [[u8; 5]; MAX_TRACKS as usize],
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::petscii_text"))]
     pub disk_name: [u8; 16],
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::petscii_text"))]
     pub disk_id: [u8; 2],
     pub dos_type: u8,
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::petscii_text"))]
+    pub format_id: [u8; 2],
+    pub layout: BamLayout,
+    pub dir_track: u8,
 }
 
 pub fn petscii_to_ascii(petscii: &[u8]) -> String {
+    petscii_to_ascii_with(petscii, CharSet::Uppercase)
+}
+
+pub fn ascii_to_petscii(ascii: &str) -> Vec<u8> {
+    ascii_to_petscii_with(ascii, CharSet::Uppercase)
+}
+
+pub fn try_ascii_to_petscii(ascii: &str) -> Result<Vec<u8>, D64Error> {
+    ascii
+        .chars()
+        .map(|c| match c {
+            ' '..='_' | 'a'..='z' => Ok(ascii_to_petscii(c.encode_utf8(&mut [0; 4]))[0]),
+            _ => Err(D64Error::UnrepresentableChar(c)),
+        })
+        .collect()
+}
+
+pub fn petscii_to_ascii_with(petscii: &[u8], charset: CharSet) -> String {
     petscii
         .iter()
         .map(|&c| match c {
+            0x41..=0x5A if charset == CharSet::Lowercase => (c + 0x20) as char,
             0x20..=0x5F => c as char,
             0xC1..=0xDA => (c - 0x80) as char,
             _ => '?',
@@ -58,10 +560,71 @@ pub fn petscii_to_ascii(petscii: &[u8]) -> String {
         .collect()
 }
 
-pub fn ascii_to_petscii(ascii: &str) -> Vec<u8> {
+pub fn petscii_to_display(petscii: &[u8]) -> String {
+    let mut display = String::new();
+    for &c in petscii {
+        match (c, control_code_name(c)) {
+            (_, Some(name)) => {
+                display.push('{');
+                display.push_str(name);
+                display.push('}');
+            }
+            (0x00..=0x1F | 0x80..=0x9F, None) => display.push_str(&format!("{{${:02X}}}", c)),
+            _ => display.push_str(&petscii_to_ascii(&[c])),
+        }
+    }
+    display
+}
+
+fn control_code_name(c: u8) -> Option<&'static str> {
+    Some(match c {
+        0x05 => "WHT",
+        0x0D => "RETURN",
+        0x0E => "LOWER",
+        0x11 => "DOWN",
+        0x12 => "RVS ON",
+        0x13 => "HOME",
+        0x14 => "DEL",
+        0x1C => "RED",
+        0x1D => "RIGHT",
+        0x1E => "GRN",
+        0x1F => "BLU",
+        0x81 => "ORNG",
+        0x85 => "F1",
+        0x86 => "F3",
+        0x87 => "F5",
+        0x88 => "F7",
+        0x89 => "F2",
+        0x8A => "F4",
+        0x8B => "F6",
+        0x8C => "F8",
+        0x8D => "SHIFT RETURN",
+        0x8E => "UPPER",
+        0x90 => "BLK",
+        0x91 => "UP",
+        0x92 => "RVS OFF",
+        0x93 => "CLR",
+        0x94 => "INST",
+        0x95 => "BRN",
+        0x96 => "LRED",
+        0x97 => "GRY1",
+        0x98 => "GRY2",
+        0x99 => "LGRN",
+        0x9A => "LBLU",
+        0x9B => "GRY3",
+        0x9C => "PUR",
+        0x9D => "LEFT",
+        0x9E => "YEL",
+        0x9F => "CYN",
+        _ => return None,
+    })
+}
+
+pub fn ascii_to_petscii_with(ascii: &str, charset: CharSet) -> Vec<u8> {
     ascii
         .chars()
         .map(|c| match c {
+            'A'..='Z' if charset == CharSet::Lowercase => (c as u8) + 0x80,
             ' '..='_' => c as u8,
             'a'..='z' => (c as u8) - 32,
             _ => 0x3F,
@@ -69,103 +632,661 @@ pub fn ascii_to_petscii(ascii: &str) -> Vec<u8> {
         .collect()
 }
 
+pub fn screencode_to_ascii(codes: &[u8]) -> String {
+    codes
+        .iter()
+        .map(|&c| match c & 0x7F {
+            c @ 0x00..=0x1F => (c + 0x40) as char,
+            c @ 0x20..=0x3F => c as char,
+            _ => '?',
+        })
+        .collect()
+}
+
+pub fn ascii_to_screencode(ascii: &str) -> Vec<u8> {
+    ascii_to_screencode_with(ascii, false)
+}
+
+pub fn ascii_to_screencode_with(ascii: &str, reverse: bool) -> Vec<u8> {
+    let reverse_bit = if reverse { 0x80 } else { 0x00 };
+    ascii
+        .chars()
+        .map(|c| match c {
+            '@'..='_' => (c as u8) - 0x40,
+            ' '..='?' => c as u8,
+            'a'..='z' => (c as u8) - 0x60,
+            _ => 0x3F,
+        })
+        .map(|code| code | reverse_bit)
+        .collect()
+}
+
+pub fn petscii_to_unicode(petscii: &[u8], charset: CharSet) -> String {
+    petscii
+        .iter()
+        .map(|&c| petscii_to_char(c, charset))
+        .collect()
+}
+
+pub fn unicode_to_petscii(text: &str, charset: CharSet) -> Vec<u8> {
+    text.chars().map(|c| char_to_petscii(c, charset)).collect()
+}
+
+fn petscii_to_char(c: u8, charset: CharSet) -> char {
+    let high = match charset {
+        CharSet::Uppercase => &PETSCII_UPPERCASE_HIGH,
+        CharSet::Lowercase => &PETSCII_LOWERCASE_HIGH,
+    };
+    match c {
+        0x20..=0x40 | 0x5B | 0x5D => c as char,
+        0x41..=0x5A if charset == CharSet::Lowercase => (c + 0x20) as char,
+        0x41..=0x5A => c as char,
+        0x5C => '£',
+        0x5E => '↑',
+        0x5F => '←',
+        0x00..=0x1F | 0x80..=0x9F => c as char,
+        0x60..=0x7F => high[(c - 0x40) as usize],
+        0xA0..=0xDF => high[(c - 0xA0) as usize],
+        0xE0..=0xFE => high[(c - 0xE0) as usize],
+        0xFF => high[0x3E],
+    }
+}
+
+fn char_to_petscii(c: char, charset: CharSet) -> u8 {
+    match (c, charset) {
+        (' '..='@' | '[' | ']', _) => c as u8,
+        ('A'..='Z', CharSet::Lowercase) => c as u8 + 0x80,
+        ('A'..='Z', CharSet::Uppercase) => c as u8,
+        ('a'..='z', _) => c as u8 - 0x20,
+        ('£', _) => 0x5C,
+        ('↑', _) => 0x5E,
+        ('←', _) => 0x5F,
+        ('\u{00}'..='\u{1F}' | '\u{80}'..='\u{9F}', _) => c as u8,
+        _ => {
+            let high = match charset {
+                CharSet::Uppercase => &PETSCII_UPPERCASE_HIGH,
+                CharSet::Lowercase => &PETSCII_LOWERCASE_HIGH,
+            };
+            high.iter()
+                .position(|&glyph| glyph == c)
+                .map_or(0x3F, |idx| 0xA0 + idx as u8)
+        }
+    }
+}
+
+fn padded_petscii<const N: usize>(text: &str) -> [u8; N] {
+    let mut padded = [0xA0; N];
+    let bytes = ascii_to_petscii(text);
+    let len = bytes.len().min(N);
+    padded[..len].copy_from_slice(&bytes[..len]);
+    padded
+}
+
+fn try_padded_petscii<const N: usize>(text: &str) -> Result<[u8; N], D64Error> {
+    try_ascii_to_petscii(text)?;
+    Ok(padded_petscii(text))
+}
+
+// CBM DOS wildcards: `?` matches any single character and `*` matches the
+// rest of the name, ignoring anything after it in the pattern.
+fn cbm_pattern_matches(pattern: &str, name: &str) -> bool {
+    let mut name_chars = name.chars();
+    for p in pattern.chars() {
+        match (p, name_chars.next()) {
+            ('*', _) => return true,
+            ('?', Some(_)) => {}
+            (p, Some(c)) if p == c => {}
+            _ => return false,
+        }
+    }
+    name_chars.next().is_none()
+}
+
+// A chain's last block stores the index of its final used byte in byte 1, so
+// a full block holds 0xFF and an empty one 0x01.
+pub(crate) fn block_content(data: &[u8]) -> Result<&[u8], D64Error> {
+    match (data[0], data[1]) {
+        (0, 0) => Err(D64Error::InvalidTrackSector),
+        (0, last) => Ok(&data[2..=last as usize]),
+        _ => Ok(&data[2..]),
+    }
+}
+
+fn entry_name(name_bytes: &[u8]) -> String {
+    let name_end = name_bytes
+        .iter()
+        .position(|&x| x == 0xA0)
+        .unwrap_or(name_bytes.len());
+    petscii_to_ascii(&name_bytes[..name_end])
+}
+
+/// Copies each span's `count` consecutive blocks, in image order and
+/// continuing onto the next track, from `span.from` in `src` to `span.to` in
+/// `dst`. Every block is validated against both geometries before anything
+/// is written.
+pub fn copy_sectors(src: &D64, dst: &mut D64, spans: &[SectorSpan]) -> Result<(), D64Error> {
+    dst.check_writable()?;
+    let mut copies = Vec::new();
+    for span in spans {
+        let (mut from, mut to) = (span.from, span.to);
+        for _ in 0..span.count {
+            copies.push((
+                src.sector_offset(from.0, from.1)?,
+                dst.sector_offset(to.0, to.1)?,
+            ));
+            from = next_block(src.tracks, from);
+            to = next_block(dst.tracks, to);
+        }
+    }
+
+    for (from, to) in copies {
+        dst.data[to..to + 256].copy_from_slice(&src.data[from..from + 256]);
+    }
+    Ok(())
+}
+
+fn next_block(tracks: u8, (track, sector): (u8, u8)) -> (u8, u8) {
+    match sectors_per_track(tracks, track) {
+        Some(sectors) if sector + 1 < sectors => (track, sector + 1),
+        _ => (track.saturating_add(1), 0),
+    }
+}
+
+pub fn sanitize_filename(name: &str, file_type: FileType) -> String {
+    let sanitized: String = name
+        .trim_end_matches([' ', '\u{a0}'])
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = sanitized.trim_matches(|c| c == ' ' || c == '.');
+    let stem = if stem.is_empty() { "_" } else { stem };
+    format!("{}.{}", stem, file_type.as_str().to_lowercase())
+}
+
 impl D64 {
+    /// Creates an all-zero, unformatted image. It has no BAM or directory, so
+    /// it reports no free blocks and every insert fails with `DiskFull`; use
+    /// [`D64::new_formatted`] or call [`D64::format`] before adding files.
     pub fn new(tracks: u8) -> Result<Self, D64Error> {
-        if tracks != 35 && tracks != 40 {
-            return Err(D64Error::InvalidFileSize);
-        }
-        let size = if tracks == 35 {
-            D64_35_TRACKS_SIZE
-        } else {
-            D64_40_TRACKS_SIZE
+        let size = match tracks {
+            35 => D64_35_TRACKS_SIZE,
+            40 => D64_40_TRACKS_SIZE,
+            41 => D64_41_TRACKS_SIZE,
+            42 => D64_42_TRACKS_SIZE,
+            D71_TRACKS => D71_70_TRACKS_SIZE,
+            D81_TRACKS => D81_80_TRACKS_SIZE,
+            _ => return Err(D64Error::InvalidFileSize),
         };
         Ok(Self {
             data: vec![0; size],
             tracks,
+            error_info: None,
+            dir_track: dir_track_for(tracks),
+            read_only: false,
         })
     }
 
+    pub fn new_formatted(tracks: u8, disk_name: &str, disk_id: &str) -> Result<Self, D64Error> {
+        let mut d64 = Self::new(tracks)?;
+        d64.format(disk_name, disk_id)?;
+        Ok(d64)
+    }
+
     pub fn format(&mut self, disk_name: &str, disk_id: &str) -> Result<(), D64Error> {
+        self.format_with_layout(disk_name, disk_id, BamLayout::default())
+    }
+
+    pub fn format_with_layout(
+        &mut self,
+        disk_name: &str,
+        disk_id: &str,
+        layout: BamLayout,
+    ) -> Result<(), D64Error> {
+        self.check_writable()?;
+        let disk_name = try_padded_petscii::<16>(disk_name)?;
+        let disk_id = try_padded_petscii::<2>(disk_id)?;
         self.data.fill(0);
+        if self.tracks == D81_TRACKS {
+            return self.format_d81(&disk_name, &disk_id);
+        }
 
         let mut bam = [0u8; 256];
-        bam[0] = 18;
+        bam[0] = self.dir_track;
         bam[1] = 1;
         bam[2] = 0x41;
 
-        for track in 1..=self.tracks {
+        let front_tracks = if self.tracks == D71_TRACKS {
+            35
+        } else {
+            self.tracks
+        };
+        for track in 1..=front_tracks {
             let track_idx = (track - 1) as usize;
             let sectors = SECTORS_PER_TRACK[track_idx];
-            bam[4 + track_idx * 4] = sectors;
-            bam[5 + track_idx * 4] = 0xFF;
-            bam[6 + track_idx * 4] = 0xFF;
-            bam[7 + track_idx * 4] = if sectors > 16 {
-                0xFF
-            } else {
-                (1 << sectors) - 1
-            };
+            let offset = layout.entry_offset(track_idx);
+            bam[offset] = sectors;
+            bam[offset + 1] = 0xFF;
+            bam[offset + 2] = 0xFF;
+            bam[offset + 3] = (1 << (sectors - 16)) - 1;
         }
 
-        for track in 18..=19 {
-            let track_idx = (track - 1) as usize;
-            bam[4 + track_idx * 4] = 0;
-            bam[5 + track_idx * 4] = 0;
-            bam[6 + track_idx * 4] = 0;
-            bam[7 + track_idx * 4] = 0;
+        bam[144..160].copy_from_slice(&disk_name);
+        bam[162..164].copy_from_slice(&disk_id);
+        bam[165..167].copy_from_slice(b"2A");
+
+        if self.tracks == D71_TRACKS {
+            bam[3] = 0x80;
+            let mut side_two = [0u8; 256];
+            for track in 36..=D71_TRACKS {
+                if track == 53 {
+                    continue;
+                }
+                let track_idx = (track - 36) as usize;
+                let sectors = SECTORS_PER_TRACK[track_idx];
+                bam[0xDD + track_idx] = sectors;
+                side_two[track_idx * 3] = 0xFF;
+                side_two[track_idx * 3 + 1] = 0xFF;
+                side_two[track_idx * 3 + 2] = (1 << (sectors - 16)) - 1;
+            }
+            self.write_sector(53, 0, &side_two)?;
+        }
+
+        self.write_sector(self.dir_track, 0, &bam)?;
+        self.with_bam(|bam| bam.reserve_directory())?;
+
+        let mut dir = [0u8; 256];
+        dir[1] = 0xFF;
+        self.write_sector(self.dir_track, 1, &dir)?;
+
+        Ok(())
+    }
+
+    // Like the 1541's `N0:NAME,ID`, but only the BAM and directory header
+    // sectors are rewritten; file data elsewhere on the disk is kept.
+    pub fn quick_format(&mut self, disk_name: &str, disk_id: &str) -> Result<(), D64Error> {
+        let layout = self
+            .read_bam()
+            .map_or(BamLayout::default(), |bam| bam.layout);
+        let previous = self.data.clone();
+        self.format_with_layout(disk_name, disk_id, layout)?;
+
+        let mut rewritten = vec![(self.dir_track, 0), (self.dir_track, 1)];
+        match self.tracks {
+            D71_TRACKS => rewritten.push((53, 0)),
+            D81_TRACKS => rewritten.extend([(self.dir_track, 2), (self.dir_track, 3)]),
+            _ => {}
+        }
+        for (track, sector) in self.all_sectors() {
+            if !rewritten.contains(&(track, sector)) {
+                let offset = self.sector_offset(track, sector)?;
+                self.data[offset..offset + 256].copy_from_slice(&previous[offset..offset + 256]);
+            }
         }
+        Ok(())
+    }
 
-        let disk_name_bytes = ascii_to_petscii(disk_name);
-        let disk_id_bytes = ascii_to_petscii(disk_id);
-        bam[144..144 + disk_name_bytes.len()].copy_from_slice(&disk_name_bytes);
-        bam[162..164].copy_from_slice(&disk_id_bytes);
+    fn format_d81(&mut self, disk_name: &[u8; 16], disk_id: &[u8; 2]) -> Result<(), D64Error> {
+        let mut header = [0u8; 256];
+        header[0] = self.dir_track;
+        header[1] = 3;
+        header[2] = 0x44;
+        header[4..0x1D].fill(0xA0);
+        header[4..0x14].copy_from_slice(disk_name);
+        header[0x16..0x18].copy_from_slice(disk_id);
+        header[0x19] = b'3';
+        header[0x1A] = b'D';
+        self.write_sector(self.dir_track, 0, &header)?;
 
-        self.write_sector(18, 0, &bam)?;
+        let mut bam = BAM {
+            tracks: D81_TRACKS,
+            free_sectors: [0; MAX_TRACKS as usize],
+            bitmap: [[0; 5]; MAX_TRACKS as usize],
+            disk_name: [0; 16],
+            disk_id: [0; 2],
+            dos_type: 0x44,
+            format_id: *b"3D",
+            layout: BamLayout::default(),
+            dir_track: self.dir_track,
+        };
+        bam.disk_name.copy_from_slice(&header[4..0x14]);
+        bam.disk_id.copy_from_slice(&header[0x16..0x18]);
+        for track in 0..D81_TRACKS as usize {
+            bam.free_sectors[track] = D81_SECTORS_PER_TRACK;
+            bam.bitmap[track] = [0xFF; 5];
+        }
+        bam.reserve_directory()?;
+        self.write_bam(&bam)?;
 
         let mut dir = [0u8; 256];
         dir[1] = 0xFF;
-        self.write_sector(18, 1, &dir)?;
+        self.write_sector(self.dir_track, 3, &dir)?;
 
         Ok(())
     }
 
     pub fn from_file(path: &str) -> Result<Self, D64Error> {
-        let mut file = File::open(path)?;
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, D64Error> {
         let mut data = Vec::new();
-        file.read_to_end(&mut data)?;
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(data)
+    }
 
-        let tracks = match data.len() {
-            D64_35_TRACKS_SIZE => 35,
-            D64_40_TRACKS_SIZE => 40,
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<Self, D64Error> {
+        let (tracks, data_size) = match data.len() {
+            D64_35_TRACKS_SIZE | D64_35_TRACKS_ERRORS_SIZE => (35, D64_35_TRACKS_SIZE),
+            D64_40_TRACKS_SIZE | D64_40_TRACKS_ERRORS_SIZE => (40, D64_40_TRACKS_SIZE),
+            D64_41_TRACKS_SIZE | D64_41_TRACKS_ERRORS_SIZE => (41, D64_41_TRACKS_SIZE),
+            D64_42_TRACKS_SIZE | D64_42_TRACKS_ERRORS_SIZE => (42, D64_42_TRACKS_SIZE),
+            D71_70_TRACKS_SIZE => (D71_TRACKS, D71_70_TRACKS_SIZE),
+            D81_80_TRACKS_SIZE => (D81_TRACKS, D81_80_TRACKS_SIZE),
             _ => return Err(D64Error::InvalidFileSize),
         };
+        let error_info = if data.len() > data_size {
+            Some(data.split_off(data_size))
+        } else {
+            None
+        };
 
-        Ok(Self { data, tracks })
+        Ok(Self {
+            data,
+            tracks,
+            error_info,
+            dir_track: dir_track_for(tracks),
+            read_only: false,
+        })
     }
 
-    pub fn save_to_file(&self, path: &str) -> Result<(), D64Error> {
-        let mut file = File::create(path)?;
-        file.write_all(&self.data)?;
-        Ok(())
+    pub fn from_file_lenient(path: &str) -> Result<Self, D64Error> {
+        Self::from_bytes_lenient(fs::read(path)?)
     }
 
-    pub fn read_sector(&self, track: u8, sector: u8) -> Result<&[u8], D64Error> {
-        let offset = self.sector_offset(track, sector)?;
-        Ok(&self.data[offset..offset + 256])
+    // Drops a footer appended by other tools. Trailing bytes are only read as
+    // error info when they are exactly the error block; otherwise the largest
+    // geometry whose BAM actually describes its tracks wins.
+    pub fn from_bytes_lenient(data: Vec<u8>) -> Result<Self, D64Error> {
+        if IMAGE_SIZES.contains(&data.len()) {
+            return Self::from_bytes(data);
+        }
+        let mut fallback = None;
+        for size in [
+            D81_80_TRACKS_SIZE,
+            D71_70_TRACKS_SIZE,
+            D64_42_TRACKS_SIZE,
+            D64_41_TRACKS_SIZE,
+            D64_40_TRACKS_SIZE,
+            D64_35_TRACKS_SIZE,
+        ] {
+            if size > data.len() {
+                continue;
+            }
+            let candidate = Self::from_bytes(data[..size].to_vec())?;
+            if candidate.has_own_geometry() {
+                return Ok(candidate);
+            }
+            fallback = Some(candidate);
+        }
+        fallback.ok_or(D64Error::InvalidFileSize)
     }
 
-    pub fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), D64Error> {
-        let offset = self.sector_offset(track, sector)?;
-        self.data[offset..offset + 256].copy_from_slice(data);
-        Ok(())
+    fn has_own_geometry(&self) -> bool {
+        match self.tracks {
+            D81_TRACKS => self
+                .read_sector(40, 0)
+                .is_ok_and(|header| header[2] == 0x44),
+            D71_TRACKS => self.read_sector(18, 0).is_ok_and(|bam| bam[3] == 0x80),
+            35 => true,
+            tracks => self.read_bam().is_ok_and(|bam| {
+                bam.is_consistent()
+                    && (36..=tracks).any(|track| bam.free_sectors[(track - 1) as usize] != 0)
+            }),
+        }
+    }
+
+    pub fn open_read_only(path: &str) -> Result<Self, D64Error> {
+        let mut d64 = Self::from_file(path)?;
+        d64.read_only = true;
+        Ok(d64)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), D64Error> {
+        if self.read_only {
+            return Err(D64Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = self.data;
+        if let Some(error_info) = self.error_info {
+            bytes.extend(error_info);
+        }
+        bytes
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<(), D64Error> {
+        self.check_writable()?;
+        self.to_writer(File::create(path)?)
+    }
+
+    /// Returns an independent, writable copy of the image. Changes to the
+    /// copy never affect `self`.
+    pub fn clone_image(&self) -> D64 {
+        let mut copy = self.clone();
+        copy.read_only = false;
+        copy
+    }
+
+    /// Writes an independent copy of the image, including any error info, to
+    /// `path`. This also works for images opened read-only.
+    pub fn clone_to_file(&self, path: &str) -> Result<(), D64Error> {
+        self.to_writer(File::create(path)?)
+    }
+
+    pub fn save_to_file_verified(&self, path: &str) -> Result<(), D64Error> {
+        self.save_to_file(path)?;
+        let mut expected = Vec::new();
+        self.to_writer(&mut expected)?;
+        if fs::read(path)? != expected {
+            return Err(D64Error::VerifyFailed);
+        }
+        Ok(())
+    }
+
+    pub fn to_writer<W: Write>(&self, mut writer: W) -> Result<(), D64Error> {
+        // The error block is stored separately, so a data area of the wrong
+        // size would silently shift it on disk.
+        if self.data.len() != self.total_sectors() * 256 {
+            return Err(D64Error::InvalidFileSize);
+        }
+        writer.write_all(&self.data)?;
+        if let Some(error_info) = &self.error_info {
+            writer.write_all(error_info)?;
+        }
+        Ok(())
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+        self.data.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    pub fn diff(&self, other: &D64) -> Vec<(u8, u8)> {
+        self.all_sectors()
+            .filter(|&(track, sector)| {
+                self.read_sector(track, sector).ok() != other.read_sector(track, sector).ok()
+            })
+            .collect()
+    }
+
+    pub fn sector_error(&self, track: u8, sector: u8) -> Option<u8> {
+        let index = self.sector_offset(track, sector).ok()? / 256;
+        self.error_info.as_ref()?.get(index).copied()
+    }
+
+    pub fn sector_data_checksum(&self, track: u8, sector: u8) -> Result<u8, D64Error> {
+        Ok(self
+            .read_sector(track, sector)?
+            .iter()
+            .fold(0, |checksum, &byte| checksum ^ byte))
+    }
+
+    // Returns (track, sector, computed checksum) for every sector that the
+    // error-info block marks with a data checksum error (23 READ ERROR).
+    pub fn verify_against_error_info(&self) -> Result<Vec<(u8, u8, u8)>, D64Error> {
+        let mut flagged = Vec::new();
+        for track in 1..=self.tracks {
+            for sector in 0..self.sectors_in_track(track)? {
+                if self.sector_error(track, sector) == Some(DATA_CHECKSUM_ERROR) {
+                    flagged.push((track, sector, self.sector_data_checksum(track, sector)?));
+                }
+            }
+        }
+        Ok(flagged)
+    }
+
+    pub fn read_sector(&self, track: u8, sector: u8) -> Result<&[u8], D64Error> {
+        let offset = self.sector_offset(track, sector)?;
+        Ok(&self.data[offset..offset + 256])
+    }
+
+    pub fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), D64Error> {
+        self.check_writable()?;
+        if data.len() != 256 {
+            return Err(D64Error::InvalidSectorLength(data.len()));
+        }
+        let offset = self.sector_offset(track, sector)?;
+        self.data[offset..offset + 256].copy_from_slice(data);
+        #[cfg(test)]
+        tests::SECTOR_WRITES.with(|writes| writes.borrow_mut().push((track, sector)));
+        debug_assert_eq!(self.data.len(), self.total_sectors() * 256);
+        Ok(())
+    }
+
+    pub fn boot_info(&self) -> Result<BootInfo, D64Error> {
+        let sector = self.read_sector(1, 0)?;
+        let c128_boot = sector.starts_with(b"CBM");
+        let message = c128_boot.then(|| {
+            let text = &sector[7..];
+            let end = text
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(text.len());
+            petscii_to_ascii(&text[..end])
+        });
+
+        let in_file = self
+            .list_entries()?
+            .iter()
+            .filter_map(|entry| self.trace_chain(entry.start_track, entry.start_sector).ok())
+            .any(|chain| chain.contains(&(1, 0)));
+        let non_empty = sector.iter().any(|&byte| byte != 0);
+
+        Ok(BootInfo {
+            c128_boot,
+            message,
+            likely_autostart: c128_boot || (non_empty && !in_file),
+            first_bytes: sector[..16].to_vec(),
+        })
+    }
+
+    pub fn read_track(&self, track: u8) -> Result<Vec<u8>, D64Error> {
+        let sectors = self.sectors_in_track(track)?;
+        let offset = self.sector_offset(track, 0)?;
+        Ok(self.data[offset..offset + sectors as usize * 256].to_vec())
+    }
+
+    pub fn write_track(&mut self, track: u8, data: &[u8]) -> Result<(), D64Error> {
+        self.check_writable()?;
+        let sectors = self.sectors_in_track(track)?;
+        if data.len() != sectors as usize * 256 {
+            return Err(D64Error::InvalidTrackLength(data.len()));
+        }
+        let offset = self.sector_offset(track, 0)?;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        debug_assert_eq!(self.data.len(), self.total_sectors() * 256);
+        Ok(())
+    }
+
+    pub fn hexdump_sector(&self, track: u8, sector: u8) -> Result<String, D64Error> {
+        let data = self.read_sector(track, sector)?;
+        let mut dump = String::new();
+        for (row, chunk) in data.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            dump.push_str(&format!(
+                "{:02X}: {}  |{}|\n",
+                row * 16,
+                hex.join(" "),
+                petscii_to_ascii(chunk)
+            ));
+        }
+        Ok(dump)
     }
 
     pub fn trace_file(&self, filename: &str) -> Result<Vec<(u8, u8)>, D64Error> {
         let (start_track, start_sector) = self.find_file(filename)?;
+        self.trace_chain(start_track, start_sector)
+    }
+
+    pub fn file_block_count(&self, filename: &str) -> Result<u16, D64Error> {
+        Ok(self.trace_file(filename)?.len() as u16)
+    }
+
+    pub fn disk_usage(&self) -> Result<Vec<FileUsage>, D64Error> {
+        self.list_entries()?
+            .into_iter()
+            .map(|entry| {
+                let blocks = self
+                    .trace_chain(entry.start_track, entry.start_sector)?
+                    .len();
+                Ok(FileUsage {
+                    name: entry.name,
+                    blocks: blocks as u16,
+                    stored_blocks: entry.size_blocks,
+                })
+            })
+            .collect()
+    }
+
+    pub fn trace_file_by_index(&self, index: usize) -> Result<Vec<(u8, u8)>, D64Error> {
+        let entry = self.entry_by_index(index)?;
+        self.trace_chain(entry.start_track, entry.start_sector)
+    }
+
+    pub fn next_link(&self, track: u8, sector: u8) -> Result<Option<(u8, u8)>, D64Error> {
+        let data = self.read_sector(track, sector)?;
+        if data[0] == 0 {
+            return Ok(None);
+        }
+        self.sector_offset(data[0], data[1])?;
+        Ok(Some((data[0], data[1])))
+    }
+
+    fn trace_chain(&self, start_track: u8, start_sector: u8) -> Result<Vec<(u8, u8)>, D64Error> {
         let mut sectors = Vec::new();
         let mut track = start_track;
         let mut sector = start_sector;
+        let mut visited_sectors = HashSet::new();
 
         loop {
+            if !visited_sectors.insert((track, sector)) {
+                return Err(D64Error::InvalidTrackSector);
+            }
             sectors.push((track, sector));
             let data = self.read_sector(track, sector)?;
             let next_track = data[0];
@@ -181,236 +1302,1017 @@ impl D64 {
         Ok(sectors)
     }
 
+    pub fn sectors_in_track(&self, track: u8) -> Result<u8, D64Error> {
+        sectors_per_track(self.tracks, track).ok_or(D64Error::InvalidTrackSector)
+    }
+
+    pub fn all_sectors(&self) -> impl Iterator<Item = (u8, u8)> {
+        let tracks = self.tracks;
+        (1..=tracks).flat_map(move |track| {
+            (0..sectors_per_track(tracks, track).unwrap_or(0)).map(move |sector| (track, sector))
+        })
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        (1..=self.tracks)
+            .filter_map(|track| sectors_per_track(self.tracks, track))
+            .map(|sectors| sectors as usize)
+            .sum()
+    }
+
     fn sector_offset(&self, track: u8, sector: u8) -> Result<usize, D64Error> {
-        if track == 0 || track > self.tracks || sector >= SECTORS_PER_TRACK[(track - 1) as usize] {
+        match sectors_per_track(self.tracks, track) {
+            Some(sectors) if sector < sectors => {}
+            _ => return Err(D64Error::InvalidTrackSector),
+        }
+
+        let track_start = if self.tracks == D81_TRACKS {
+            (track - 1) as usize * D81_SECTORS_PER_TRACK as usize * 256
+        } else if self.tracks == D71_TRACKS && track > 35 {
+            TRACK_OFFSETS[35] + TRACK_OFFSETS[(track - 36) as usize]
+        } else {
+            TRACK_OFFSETS[(track - 1) as usize]
+        };
+
+        Ok(track_start + sector as usize * 256)
+    }
+
+    pub fn list_files(&self) -> Result<Vec<String>, D64Error> {
+        Ok(self
+            .list_entries()?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    pub fn find_files(&self, pattern: &str) -> Result<Vec<String>, D64Error> {
+        Ok(self
+            .list_files()?
+            .into_iter()
+            .filter(|name| cbm_pattern_matches(pattern, name))
+            .collect())
+    }
+
+    pub fn list_entries(&self) -> Result<Vec<DirEntry>, D64Error> {
+        self.entries().collect()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = Result<DirEntry, D64Error>> + '_ {
+        let sector = self.first_dir_sector();
+        DirEntries {
+            d64: self,
+            sector,
+            slot: 0,
+            visited_sectors: HashSet::from([sector]),
+            done: false,
+        }
+    }
+
+    pub fn directory_slots(&self) -> Result<Vec<DirSlot>, D64Error> {
+        let mut slots = Vec::new();
+        for (track, sector) in self.directory_chain()? {
+            let data = self.read_sector(track, sector)?;
+            for offset in (0..256).step_by(32) {
+                let mut slot = [0u8; 32];
+                slot.copy_from_slice(&data[offset..offset + 32]);
+                slots.push((track, sector, offset, slot));
+            }
+        }
+        Ok(slots)
+    }
+
+    pub fn list_scratched(&self) -> Result<Vec<ScratchedEntry>, D64Error> {
+        let mut scratched = Vec::new();
+        for (_, _, _, slot) in self.directory_slots()? {
+            let plausible_start =
+                sectors_per_track(self.tracks, slot[3]).is_some_and(|sectors| slot[4] < sectors);
+            if slot[2] != 0x00 || !plausible_start || matches!(slot[5], 0x00 | 0xA0) {
+                continue;
+            }
+            scratched.push(ScratchedEntry {
+                name: entry_name(&slot[5..21]),
+                start_track: slot[3],
+                start_sector: slot[4],
+            });
+        }
+        Ok(scratched)
+    }
+
+    pub fn write_dir_slot(
+        &mut self,
+        track: u8,
+        sector: u8,
+        byte_offset: usize,
+        entry: &[u8; 32],
+    ) -> Result<(), D64Error> {
+        if track != self.dir_track
+            || !byte_offset.is_multiple_of(32)
+            || byte_offset >= 256
+            || !self.directory_chain()?.contains(&(track, sector))
+        {
             return Err(D64Error::InvalidTrackSector);
         }
 
-        let mut offset = 0;
-        for t in 1..track {
-            offset += SECTORS_PER_TRACK[(t - 1) as usize] as usize * 256;
+        // The first slot's leading bytes are the directory chain link, which
+        // is owned by the sector rather than the entry.
+        let skip = if byte_offset == 0 { 2 } else { 0 };
+        let mut data = self.read_sector(track, sector)?.to_vec();
+        data[byte_offset + skip..byte_offset + 32].copy_from_slice(&entry[skip..]);
+        self.write_sector(track, sector, &data)
+    }
+
+    pub fn move_entry(&mut self, from_index: usize, to_index: usize) -> Result<(), D64Error> {
+        let live: Vec<_> = self
+            .directory_slots()?
+            .into_iter()
+            .filter(|(_, _, _, slot)| DirEntry::from_slot(slot).is_some())
+            .collect();
+        if from_index >= live.len() || to_index >= live.len() {
+            return Err(D64Error::FileNotFound);
         }
-        offset += sector as usize * 256;
 
-        Ok(offset)
+        let mut entries: Vec<[u8; 32]> = live.iter().map(|&(_, _, _, slot)| slot).collect();
+        let moved = entries.remove(from_index);
+        entries.insert(to_index, moved);
+        for (&(track, sector, offset, _), mut slot) in live.iter().zip(entries) {
+            slot[..2].fill(0);
+            self.write_dir_slot(track, sector, offset, &slot)?;
+        }
+        Ok(())
     }
 
-    pub fn list_files(&self) -> Result<Vec<String>, D64Error> {
-        let mut files = Vec::new();
-        let dir_track = 18;
-        let mut sector = 1;
-        let mut visited_sectors = std::collections::HashSet::new();
+    fn directory_chain(&self) -> Result<Vec<(u8, u8)>, D64Error> {
+        let mut chain = Vec::new();
+        let dir_track = self.dir_track;
+        let first_sector = self.first_dir_sector();
+        let mut sector = first_sector;
+        let mut visited_sectors = HashSet::new();
 
         loop {
             if visited_sectors.contains(&(dir_track, sector)) {
                 return Err(D64Error::InvalidTrackSector);
             }
-            visited_sectors.insert((dir_track, sector));
+            visited_sectors.insert((dir_track, sector));
+            chain.push((dir_track, sector));
+
+            let data = self.read_sector(dir_track, sector)?;
+            let next_track = data[0];
+            let next_sector = data[1];
+
+            if next_track == 0 || (next_track == dir_track && next_sector == first_sector) {
+                break;
+            }
+
+            if next_track != dir_track
+                || next_sector >= sectors_per_track(self.tracks, dir_track).unwrap_or(0)
+            {
+                return Err(D64Error::InvalidTrackSector);
+            }
+
+            sector = next_sector;
+        }
+
+        Ok(chain)
+    }
+
+    pub fn validate(&self) -> Result<ValidationReport, D64Error> {
+        let bam = self.read_bam()?;
+        let mut claims: HashMap<(u8, u8), usize> = HashMap::new();
+
+        for block in self.system_blocks()? {
+            *claims.entry(block).or_insert(0) += 1;
+        }
+        for entry in self.list_entries()? {
+            for block in self.entry_blocks(&entry)? {
+                *claims.entry(block).or_insert(0) += 1;
+            }
+        }
+
+        let mut report = ValidationReport::default();
+        for track in 1..=self.tracks {
+            for sector in 0..sectors_per_track(self.tracks, track).unwrap_or(0) {
+                let free = bam.is_sector_free(track, sector);
+                match claims.get(&(track, sector)).copied().unwrap_or(0) {
+                    0 if !free => report.push(track, sector, Discrepancy::AllocatedButUnused),
+                    0 => {}
+                    count => {
+                        if free {
+                            report.push(track, sector, Discrepancy::UsedButFree);
+                        }
+                        if count > 1 {
+                            report.push(track, sector, Discrepancy::CrossLinked);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn compact(&mut self) -> Result<(), D64Error> {
+        self.check_writable()?;
+        // Every slot with a type byte is kept, including closed DEL entries
+        // that `DirEntry` skips.
+        let mut files = Vec::new();
+        for (_, _, _, slot) in self.directory_slots()? {
+            if slot[2] == 0 {
+                continue;
+            }
+            if DirEntry::from_slot(&slot)
+                .is_some_and(|entry| entry.file_type == FileType::Rel || entry.geos.is_some())
+            {
+                return Err(D64Error::CannotRelocate);
+            }
+            let content = match slot[3] {
+                0 => None,
+                track => Some(self.read_chain(track, slot[4])?),
+            };
+            files.push((slot, content));
+        }
+
+        // Rebuild into a copy so a failure leaves the image untouched.
+        let bam = self.read_bam()?;
+        let mut compacted = self.clone();
+        compacted.format_with_layout("", "", bam.layout)?;
+        let mut new_bam = compacted.read_bam()?;
+        new_bam.disk_name = bam.disk_name;
+        new_bam.disk_id = bam.disk_id;
+        new_bam.dos_type = bam.dos_type;
+        new_bam.format_id = bam.format_id;
+
+        // Keep the original slot bytes, replacing only the start block and the
+        // recounted block size.
+        for (mut slot, content) in files {
+            if let Some(content) = content {
+                let start = new_bam.first_free_sector(false)?;
+                let block_count = content.len().div_ceil(254).max(1);
+                let blocks = new_bam.allocate_chain(start, block_count, DEFAULT_INTERLEAVE)?;
+                compacted.write_chain(&blocks, &content)?;
+                (slot[3], slot[4]) = start;
+                slot[30..32].copy_from_slice(&(blocks.len() as u16).to_le_bytes());
+            }
+            compacted.write_dir_entry(&mut new_bam, slot)?;
+        }
+        compacted.write_bam(&new_bam)?;
+
+        *self = compacted;
+        Ok(())
+    }
+
+    pub fn rebuild_bam(&mut self) -> Result<(), D64Error> {
+        let mut bam = self.read_bam()?;
+        bam.free_all();
+
+        for (track, sector) in self.system_blocks()? {
+            bam.allocate_sector(track, sector)?;
+        }
+        for entry in self.list_entries()? {
+            for (track, sector) in self.entry_blocks(&entry)? {
+                bam.allocate_sector(track, sector)?;
+            }
+        }
+
+        self.write_bam(&bam)
+    }
+
+    // Besides the data chain, a REL file owns its side-sector chain and a GEOS
+    // file its info block and, for VLIR, the chain of every record.
+    fn entry_blocks(&self, entry: &DirEntry) -> Result<Vec<(u8, u8)>, D64Error> {
+        let mut blocks = self.trace_chain(entry.start_track, entry.start_sector)?;
+        if let (Some(track), Some(sector)) = (entry.side_sector_track, entry.side_sector_sector) {
+            blocks.extend(self.trace_chain(track, sector)?);
+        }
+        if let Some(geos) = entry.geos {
+            if geos.info_track != 0 {
+                blocks.push((geos.info_track, geos.info_sector));
+            }
+            if geos.vlir {
+                let index = self.read_sector(entry.start_track, entry.start_sector)?;
+                for record in index[2..].chunks(2) {
+                    match (record[0], record[1]) {
+                        (0, 0) => break,
+                        (0, _) => continue,
+                        (track, sector) => blocks.extend(self.trace_chain(track, sector)?),
+                    }
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn system_blocks(&self) -> Result<Vec<(u8, u8)>, D64Error> {
+        let mut blocks = match self.tracks {
+            D81_TRACKS => vec![
+                (self.dir_track, 0),
+                (self.dir_track, 1),
+                (self.dir_track, 2),
+            ],
+            D71_TRACKS => {
+                let mut blocks = vec![(self.dir_track, 0)];
+                blocks.extend((0..SECTORS_PER_TRACK[17]).map(|sector| (53, sector)));
+                blocks
+            }
+            _ => vec![(self.dir_track, 0)],
+        };
+        blocks.extend(self.directory_chain()?);
+        Ok(blocks)
+    }
+
+    pub fn format_directory(&self) -> Result<String, D64Error> {
+        let bam = self.read_bam()?;
+        let mut listing = format!(
+            "0 \"{:<16}\" {} {}\n",
+            bam.get_disk_name(),
+            bam.get_disk_id(),
+            bam.get_format_id()
+        );
+
+        for entry in self.list_entries()? {
+            let quoted_name = format!("\"{}\"", entry.name);
+            let splat = if entry.is_closed { ' ' } else { '*' };
+            let lock = if entry.locked { "<" } else { "" };
+            listing.push_str(&format!(
+                "{:<5}{:<18}{}{}{}\n",
+                entry.size_blocks, quoted_name, splat, entry.file_type, lock
+            ));
+        }
+
+        listing.push_str(&format!("{} BLOCKS FREE.\n", self.free_blocks()?));
+
+        Ok(listing)
+    }
+
+    pub fn free_blocks(&self) -> Result<u16, D64Error> {
+        let bam = self.read_bam()?;
+        let dir_track_free = bam.get_free_sectors_count(self.dir_track)? as u16;
+        Ok(bam.total_free_sectors() - dir_track_free)
+    }
+
+    pub fn extract_file(&self, filename: &str) -> Result<Vec<u8>, D64Error> {
+        let (start_track, start_sector) = self.find_file(filename)?;
+        self.read_chain(start_track, start_sector)
+    }
+
+    pub fn extract_file_by_index(&self, index: usize) -> Result<Vec<u8>, D64Error> {
+        let entry = self.entry_by_index(index)?;
+        self.read_chain(entry.start_track, entry.start_sector)
+    }
+
+    fn entry_by_index(&self, index: usize) -> Result<DirEntry, D64Error> {
+        self.entries().nth(index).ok_or(D64Error::FileNotFound)?
+    }
+
+    pub fn extract_geos(&self, filename: &str) -> Result<Vec<u8>, D64Error> {
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let slot = &self.read_sector(self.dir_track, dir_sector)?[offset..offset + 32];
+        let vlir = DirEntry::from_slot(slot)
+            .and_then(|entry| entry.geos)
+            .is_some_and(|geos| geos.vlir);
+        if !vlir {
+            return self.read_chain(slot[3], slot[4]);
+        }
+
+        let index = self.read_sector(slot[3], slot[4])?;
+        let mut content = Vec::new();
+        for record in index[2..].chunks(2) {
+            match (record[0], record[1]) {
+                (0, 0) => break,
+                (0, _) => continue,
+                (track, sector) => content.extend(self.read_chain(track, sector)?),
+            }
+        }
+        Ok(content)
+    }
+
+    pub fn extract_all(&self, out_dir: &Path) -> Result<Vec<(String, PathBuf)>, D64Error> {
+        fs::create_dir_all(out_dir)?;
+        let mut used = HashSet::new();
+        let mut extracted = Vec::new();
+
+        for entry in self.list_entries()? {
+            let base = sanitize_filename(&entry.name, entry.file_type);
+            let (stem, extension) = base.rsplit_once('.').unwrap_or((&base, ""));
+            let mut file_name = base.clone();
+            let mut suffix = 1;
+            while used.contains(&file_name) || out_dir.join(&file_name).exists() {
+                file_name = format!("{}_{}.{}", stem, suffix, extension);
+                suffix += 1;
+            }
+
+            let path = out_dir.join(&file_name);
+            let content = self.read_chain(entry.start_track, entry.start_sector)?;
+            fs::write(&path, content)?;
+            used.insert(file_name);
+            extracted.push((entry.name, path));
+        }
+
+        Ok(extracted)
+    }
+
+    pub fn extract_file_to<W: Write>(&self, filename: &str, mut out: W) -> Result<(), D64Error> {
+        let (start_track, start_sector) = self.find_file(filename)?;
+        self.stream_chain(start_track, start_sector, &mut out)
+    }
+
+    fn read_chain(&self, start_track: u8, start_sector: u8) -> Result<Vec<u8>, D64Error> {
+        let mut content = Vec::new();
+        self.stream_chain(start_track, start_sector, &mut content)?;
+        Ok(content)
+    }
+
+    fn stream_chain<W: Write>(
+        &self,
+        start_track: u8,
+        start_sector: u8,
+        out: &mut W,
+    ) -> Result<(), D64Error> {
+        let mut track = start_track;
+        let mut sector = start_sector;
+        let mut visited_sectors = HashSet::new();
+
+        loop {
+            if !visited_sectors.insert((track, sector)) {
+                return Err(D64Error::InvalidTrackSector);
+            }
+            let data = self.read_sector(track, sector)?;
+            let next_track = data[0];
+            let next_sector = data[1];
+            out.write_all(block_content(data)?)?;
+
+            if next_track == 0 {
+                break;
+            }
+            track = next_track;
+            sector = next_sector;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_address(&self, filename: &str) -> Result<Option<u16>, D64Error> {
+        let (track, sector) = self.find_file(filename)?;
+        let content = block_content(self.read_sector(track, sector)?)?;
+        if content.len() < 2 {
+            return Ok(None);
+        }
+        Ok(Some(u16::from_le_bytes([content[0], content[1]])))
+    }
+
+    pub fn set_load_address(&mut self, filename: &str, addr: u16) -> Result<(), D64Error> {
+        let (track, sector) = self.find_file(filename)?;
+        let mut data = self.read_sector(track, sector)?.to_vec();
+        if block_content(&data)?.len() < 2 {
+            return Err(D64Error::FileTooShort);
+        }
+        data[2..4].copy_from_slice(&addr.to_le_bytes());
+        self.write_sector(track, sector, &data)
+    }
+
+    pub fn extract_prg(&self, filename: &str) -> Result<(u16, Vec<u8>), D64Error> {
+        let content = self.extract_file(filename)?;
+        if content.len() < 2 {
+            return Err(D64Error::FileTooShort);
+        }
+        let load_addr = u16::from_le_bytes([content[0], content[1]]);
+        Ok((load_addr, content[2..].to_vec()))
+    }
+
+    pub fn import_prg(
+        &mut self,
+        filename: &str,
+        load_addr: u16,
+        body: &[u8],
+    ) -> Result<(), D64Error> {
+        let mut content = load_addr.to_le_bytes().to_vec();
+        content.extend_from_slice(body);
+        self.insert_file(filename, &content)
+    }
+
+    pub fn insert_file(&mut self, filename: &str, content: &[u8]) -> Result<(), D64Error> {
+        self.insert_file_typed(filename, content, FileType::Prg)
+    }
+
+    pub fn insert_file_typed(
+        &mut self,
+        filename: &str,
+        content: &[u8],
+        file_type: FileType,
+    ) -> Result<(), D64Error> {
+        // A DEL entry is skipped by directory lookups and a REL file needs side
+        // sectors, so neither can be written as a plain chain.
+        if matches!(file_type, FileType::Del | FileType::Rel) {
+            return Err(D64Error::UnsupportedFileType(file_type));
+        }
+        self.write_file(
+            filename,
+            content,
+            file_type,
+            InterleavePolicy::Standard,
+            None,
+        )
+    }
+
+    // The single data block is linked as `00 01`, holding no bytes yet, so
+    // `append_file` can grow it.
+    pub fn create_empty_file(
+        &mut self,
+        filename: &str,
+        file_type: FileType,
+    ) -> Result<(), D64Error> {
+        self.insert_file_typed(filename, &[], file_type)
+    }
+
+    pub fn insert_host_file(
+        &mut self,
+        path: &Path,
+        filename: &str,
+        file_type: FileType,
+    ) -> Result<(), D64Error> {
+        let content = fs::read(path)?;
+        self.insert_file_typed(filename, &content, file_type)
+    }
+
+    pub fn insert_file_with_interleave(
+        &mut self,
+        filename: &str,
+        content: &[u8],
+        interleave: u8,
+    ) -> Result<(), D64Error> {
+        self.insert_file_with_policy(filename, content, InterleavePolicy::Custom(interleave))
+    }
+
+    pub fn insert_file_with_policy(
+        &mut self,
+        filename: &str,
+        content: &[u8],
+        policy: InterleavePolicy,
+    ) -> Result<(), D64Error> {
+        self.write_file(filename, content, FileType::Prg, policy, None)
+    }
+
+    pub fn insert_file_at(
+        &mut self,
+        filename: &str,
+        content: &[u8],
+        start_track: u8,
+        start_sector: u8,
+    ) -> Result<(), D64Error> {
+        self.write_file(
+            filename,
+            content,
+            FileType::Prg,
+            InterleavePolicy::Standard,
+            Some((start_track, start_sector)),
+        )
+    }
+
+    pub fn can_fit(&self, content_len: usize) -> Result<bool, D64Error> {
+        let blocks_needed = content_len.div_ceil(254).max(1);
+        if blocks_needed > self.free_blocks()? as usize {
+            return Ok(false);
+        }
+
+        for (track, sector) in self.directory_chain()? {
+            let data = self.read_sector(track, sector)?;
+            if (0..256).step_by(32).any(|i| data[i + 2] == 0) {
+                return Ok(true);
+            }
+        }
+        let bam = self.read_bam()?;
+        Ok(bam.get_free_sectors_count(self.dir_track)? > 0)
+    }
 
-            let data = self.read_sector(dir_track, sector)?;
+    fn write_file(
+        &mut self,
+        filename: &str,
+        content: &[u8],
+        file_type: FileType,
+        policy: InterleavePolicy,
+        start: Option<(u8, u8)>,
+    ) -> Result<(), D64Error> {
+        if !self.can_fit(content.len())? {
+            return Err(D64Error::DiskFull);
+        }
 
-            for i in (0..256).step_by(32) {
-                let file_type = data[i + 2];
-                if file_type == 0 {
-                    continue;
-                }
-                if file_type != 0 && file_type & 0x07 != 0 {
-                    let name_end = data[i + 5..i + 21]
-                        .iter()
-                        .position(|&x| x == 0xA0)
-                        .unwrap_or(16);
-                    let name = petscii_to_ascii(&data[i + 5..i + 5 + name_end]);
-                    files.push(name);
+        self.with_bam_and_sectors(|d64, bam| {
+            let (track, sector) = match start {
+                Some((track, sector)) => {
+                    d64.sector_offset(track, sector)?;
+                    if !bam.is_sector_free(track, sector) {
+                        return Err(D64Error::SectorInUse);
+                    }
+                    (track, sector)
                 }
-            }
+                None => bam.first_free_sector(false)?,
+            };
+            let mut dir_entry = d64.create_dir_entry(filename, file_type, track, sector)?;
 
-            let next_track = data[0];
-            let next_sector = data[1];
+            let block_count = content.len().div_ceil(254).max(1);
+            let blocks = bam.allocate_chain((track, sector), block_count, policy.interleave())?;
+            dir_entry[30..32].copy_from_slice(&(blocks.len() as u16).to_le_bytes());
 
-            if next_track == 0 || (next_track == 18 && next_sector == 1) {
-                break;
-            }
+            d64.write_dir_entry(bam, dir_entry)?;
+            d64.write_chain(&blocks, content)
+        })
+    }
 
-            if next_track != 18 || next_sector >= SECTORS_PER_TRACK[17] {
-                return Err(D64Error::InvalidTrackSector);
+    pub fn append_file(&mut self, filename: &str, extra: &[u8]) -> Result<(), D64Error> {
+        let dir_track = self.dir_track;
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let mut dir_data = self.read_sector(dir_track, dir_sector)?.to_vec();
+        let mut blocks = self.trace_chain(dir_data[offset + 3], dir_data[offset + 4])?;
+
+        let (last_track, last_sector) = blocks[blocks.len() - 1];
+        let mut last_data = self.read_sector(last_track, last_sector)?.to_vec();
+        let used = block_content(&last_data)?.len();
+        let fill = extra.len().min(254 - used);
+        let overflow = &extra[fill..];
+
+        let new_count = overflow.len().div_ceil(254);
+        if new_count > self.free_blocks()? as usize {
+            return Err(D64Error::DiskFull);
+        }
+        let new_blocks = self.with_bam(|bam| {
+            let mut new_blocks = Vec::with_capacity(new_count);
+            let (mut track, mut sector) = (last_track, last_sector);
+            for _ in 0..new_count {
+                (track, sector) = bam.next_free_sector(track, sector, DEFAULT_INTERLEAVE)?;
+                bam.allocate_sector(track, sector)?;
+                new_blocks.push((track, sector));
             }
-
-            sector = next_sector;
+            Ok(new_blocks)
+        })?;
+
+        last_data[2 + used..2 + used + fill].copy_from_slice(&extra[..fill]);
+        match new_blocks.first() {
+            Some(&(next_track, next_sector)) => {
+                last_data[0] = next_track;
+                last_data[1] = next_sector;
+            }
+            None => last_data[1] = (used + fill + 1) as u8,
         }
+        self.write_sector(last_track, last_sector, &last_data)?;
+        self.write_chain(&new_blocks, overflow)?;
 
-        Ok(files)
+        blocks.extend(new_blocks);
+        dir_data[offset + 30..offset + 32].copy_from_slice(&(blocks.len() as u16).to_le_bytes());
+        self.write_sector(dir_track, dir_sector, &dir_data)
     }
 
-    pub fn extract_file(&self, filename: &str) -> Result<Vec<u8>, D64Error> {
-        let (start_track, start_sector) = self.find_file(filename)?;
-        let mut content = Vec::new();
-        let mut track = start_track;
-        let mut sector = start_sector;
-
-        loop {
-            let data = self.read_sector(track, sector)?;
-            let next_track = data[0];
-            let next_sector = data[1];
-            let bytes_to_read = if next_track == 0 { next_sector } else { 254 };
-            content.extend_from_slice(&data[2..2 + bytes_to_read as usize]);
-
-            if next_track == 0 {
-                break;
+    fn write_chain(&mut self, blocks: &[(u8, u8)], content: &[u8]) -> Result<(), D64Error> {
+        for (i, &(track, sector)) in blocks.iter().enumerate() {
+            let chunk = &content[i * 254..content.len().min((i + 1) * 254)];
+            let mut sector_data = vec![0; 256];
+            match blocks.get(i + 1) {
+                Some(&(next_track, next_sector)) => {
+                    sector_data[0] = next_track;
+                    sector_data[1] = next_sector;
+                }
+                None => sector_data[1] = chunk.len() as u8 + 1,
             }
-            track = next_track;
-            sector = next_sector;
+            sector_data[2..2 + chunk.len()].copy_from_slice(chunk);
+            self.write_sector(track, sector, &sector_data)?;
         }
-
-        Ok(content)
+        Ok(())
     }
 
-    pub fn insert_file(&mut self, filename: &str, content: &[u8]) -> Result<(), D64Error> {
-        let (mut track, mut sector) = self.find_free_sector()?;
-        let mut remaining = content;
+    pub fn delete_file(&mut self, filename: &str) -> Result<(), D64Error> {
+        self.delete_file_with(filename, false)
+    }
 
-        let dir_entry = self.create_dir_entry(filename, track, sector)?;
-        self.write_dir_entry(dir_entry)?;
+    pub fn delete_file_with(&mut self, filename: &str, force: bool) -> Result<(), D64Error> {
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let mut dir_data = self.read_sector(self.dir_track, dir_sector)?.to_vec();
+        if dir_data[offset + 2] & 0x40 != 0 && !force {
+            return Err(D64Error::FileLocked);
+        }
+        let sectors = self.trace_chain(dir_data[offset + 3], dir_data[offset + 4])?;
 
-        while !remaining.is_empty() {
-            let mut sector_data = vec![0; 256];
-            let (next_track, next_sector) = if remaining.len() > 254 {
-                sector_data[0] = track;
-                sector_data[1] = sector + 1;
-                if sector + 1 >= SECTORS_PER_TRACK[(track - 1) as usize] {
-                    (track + 1, 0)
-                } else {
-                    (track, sector + 1)
-                }
-            } else {
-                sector_data[0] = 0;
-                sector_data[1] = remaining.len() as u8;
-                (0, 0)
-            };
+        self.with_bam(|bam| {
+            sectors
+                .into_iter()
+                .try_for_each(|(track, sector)| bam.free_sector(track, sector))
+        })?;
 
-            let bytes_to_write = remaining.len().min(254);
-            sector_data[2..2 + bytes_to_write].copy_from_slice(&remaining[..bytes_to_write]);
-            self.write_sector(track, sector, &sector_data)?;
+        dir_data[offset + 2] = 0x00;
+        self.write_sector(self.dir_track, dir_sector, &dir_data)
+    }
 
-            remaining = &remaining[bytes_to_write..];
-            track = next_track;
-            sector = next_sector;
+    pub fn set_locked(&mut self, filename: &str, locked: bool) -> Result<(), D64Error> {
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let mut dir_data = self.read_sector(self.dir_track, dir_sector)?.to_vec();
+        if locked {
+            dir_data[offset + 2] |= 0x40;
+        } else {
+            dir_data[offset + 2] &= !0x40;
+        }
+        self.write_sector(self.dir_track, dir_sector, &dir_data)
+    }
 
-            if track == 0 {
-                break;
-            }
+    pub fn rename_file(&mut self, old: &str, new: &str) -> Result<(), D64Error> {
+        let name_bytes = ascii_to_petscii(new);
+        if name_bytes.len() > 16 {
+            return Err(D64Error::NameTooLong);
         }
 
-        Ok(())
+        let (dir_sector, offset) = self.find_dir_entry(old)?;
+        let mut dir_data = self.read_sector(self.dir_track, dir_sector)?.to_vec();
+        dir_data[offset + 5..offset + 21].fill(0xA0);
+        dir_data[offset + 5..offset + 5 + name_bytes.len()].copy_from_slice(&name_bytes);
+        self.write_sector(self.dir_track, dir_sector, &dir_data)
     }
 
     fn find_file(&self, filename: &str) -> Result<(u8, u8), D64Error> {
-        let dir_track = 18;
-        let mut sector = 1;
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let data = self.read_sector(self.dir_track, dir_sector)?;
+        Ok((data[offset + 3], data[offset + 4]))
+    }
 
-        loop {
-            let data = self.read_sector(dir_track, sector)?;
+    fn find_dir_entry(&self, filename: &str) -> Result<(u8, usize), D64Error> {
+        for (track, sector) in self.directory_chain()? {
+            let data = self.read_sector(track, sector)?;
             for i in (0..256).step_by(32) {
                 let file_type = data[i + 2];
-                if file_type != 0 && file_type & 0x07 != 0 {
-                    let name = petscii_to_ascii(&data[i + 5..i + 21]);
-                    if name.trim() == filename {
-                        return Ok((data[i + 3], data[i + 4]));
-                    }
+                if file_type != 0
+                    && file_type & 0x07 != 0
+                    && entry_name(&data[i + 5..i + 21]) == filename
+                {
+                    return Ok((sector, i));
                 }
             }
-            sector = data[1];
-            if sector == 0 {
-                break;
-            }
         }
 
         Err(D64Error::FileNotFound)
     }
 
+    fn first_dir_sector(&self) -> u8 {
+        if self.tracks == D81_TRACKS {
+            3
+        } else {
+            1
+        }
+    }
+
+    pub fn block_map(&self) -> Result<Vec<Vec<bool>>, D64Error> {
+        let bam = self.read_bam()?;
+        Ok((1..=self.tracks)
+            .map(|track| {
+                let sectors = sectors_per_track(self.tracks, track).unwrap_or(0);
+                (0..sectors)
+                    .map(|sector| !bam.is_sector_free(track, sector))
+                    .collect()
+            })
+            .collect())
+    }
+
     pub fn read_bam(&self) -> Result<BAM, D64Error> {
-        let bam_data = self.read_sector(18, 0)?;
-        BAM::from_sector_data(bam_data, self.tracks)
+        let mut bam = if self.tracks == D81_TRACKS {
+            BAM::from_d81_sectors(
+                self.read_sector(self.dir_track, 0)?,
+                self.read_sector(self.dir_track, 1)?,
+                self.read_sector(self.dir_track, 2)?,
+            )?
+        } else {
+            let bam_data = self.read_sector(self.dir_track, 0)?;
+            let mut bam = BAM::from_sector_data(bam_data, self.tracks)?;
+            if self.tracks == D71_TRACKS {
+                bam.read_second_side(self.read_sector(53, 0)?);
+            }
+            bam
+        };
+        bam.dir_track = self.dir_track;
+        Ok(bam)
     }
 
     pub fn write_bam(&mut self, bam: &BAM) -> Result<(), D64Error> {
-        let bam_data = bam.to_sector_data();
-        self.write_sector(18, 0, &bam_data)
+        if self.tracks == D81_TRACKS {
+            let mut header = self.read_sector(self.dir_track, 0)?.to_vec();
+            header[4..0x14].copy_from_slice(&bam.disk_name);
+            header[2] = bam.dos_type;
+            header[0x16..0x18].copy_from_slice(&bam.disk_id);
+            header[0x19..0x1B].copy_from_slice(&bam.format_id);
+            self.write_sector(self.dir_track, 0, &header)?;
+            self.write_sector(self.dir_track, 1, &bam.to_d81_sector_data(0))?;
+            return self.write_sector(self.dir_track, 2, &bam.to_d81_sector_data(1));
+        }
+        let mut bam_data = bam.to_sector_data();
+        bam_data[0] = self.dir_track;
+        self.write_sector(self.dir_track, 0, &bam_data)?;
+        if self.tracks == D71_TRACKS {
+            self.write_sector(53, 0, &bam.to_second_side_data())?;
+        }
+        Ok(())
     }
 
-    pub fn allocate_sector(&mut self, track: u8, sector: u8) -> Result<(), D64Error> {
+    // Reads the BAM once, lets `f` modify it and writes it back once.
+    pub fn with_bam<F, T>(&mut self, f: F) -> Result<T, D64Error>
+    where
+        F: FnOnce(&mut BAM) -> Result<T, D64Error>,
+    {
+        self.with_bam_and_sectors(|_, bam| f(bam))
+    }
+
+    // Like `with_bam`, for changes that also write other sectors of the image.
+    fn with_bam_and_sectors<F, T>(&mut self, f: F) -> Result<T, D64Error>
+    where
+        F: FnOnce(&mut Self, &mut BAM) -> Result<T, D64Error>,
+    {
+        self.check_writable()?;
         let mut bam = self.read_bam()?;
-        bam.allocate_sector(track, sector)?;
-        self.write_bam(&bam)
+        let result = f(self, &mut bam)?;
+        self.write_bam(&bam)?;
+        Ok(result)
+    }
+
+    pub fn is_allocated(&self, track: u8, sector: u8) -> Result<bool, D64Error> {
+        self.read_bam()?.is_allocated(track, sector)
+    }
+
+    pub fn allocate_sector(&mut self, track: u8, sector: u8) -> Result<(), D64Error> {
+        self.with_bam(|bam| bam.allocate_sector(track, sector))
     }
 
     pub fn free_sector(&mut self, track: u8, sector: u8) -> Result<(), D64Error> {
-        let mut bam = self.read_bam()?;
-        bam.free_sector(track, sector)?;
-        self.write_bam(&bam)
+        self.with_bam(|bam| bam.free_sector(track, sector))
+    }
+
+    pub fn allocate_next(
+        &mut self,
+        track: u8,
+        last_sector: Option<u8>,
+    ) -> Result<(u8, u8), D64Error> {
+        self.with_bam(|bam| {
+            let (track, sector) = match (last_sector, bam.find_free_sector(track)) {
+                (Some(sector), _) => bam.next_free_sector(track, sector, DEFAULT_INTERLEAVE)?,
+                (None, Some(sector)) => (track, sector),
+                (None, None) => bam.next_free_sector(track, 0, DEFAULT_INTERLEAVE)?,
+            };
+            bam.allocate_sector(track, sector)?;
+            Ok((track, sector))
+        })
     }
 
     pub fn find_free_sector(&self) -> Result<(u8, u8), D64Error> {
-        let bam = self.read_bam()?;
-        for track in 1..=self.tracks {
-            if let Some(sector) = bam.find_free_sector(track) {
-                return Ok((track, sector));
-            }
-        }
-        Err(D64Error::DiskFull)
+        self.find_free_sector_with(false)
+    }
+
+    pub fn free_blocks_list(&self) -> Result<Vec<(u8, u8)>, D64Error> {
+        Ok(self.read_bam()?.free_block_list())
+    }
+
+    pub fn find_free_sector_with(&self, allow_dir_track: bool) -> Result<(u8, u8), D64Error> {
+        self.read_bam()?.first_free_sector(allow_dir_track)
     }
 
     fn create_dir_entry(
         &self,
         filename: &str,
+        file_type: FileType,
         track: u8,
         sector: u8,
     ) -> Result<[u8; 32], D64Error> {
         let mut entry = [0u8; 32];
-        entry[2] = 0x82;
+        entry[2] = 0x80 | file_type.to_byte();
         entry[3] = track;
         entry[4] = sector;
         let name_bytes = ascii_to_petscii(filename);
+        if name_bytes.len() > 16 {
+            return Err(D64Error::NameTooLong);
+        }
+        entry[5..21].fill(0xA0);
         entry[5..5 + name_bytes.len()].copy_from_slice(&name_bytes);
         Ok(entry)
     }
 
-    fn write_dir_entry(&mut self, entry: [u8; 32]) -> Result<(), D64Error> {
-        let dir_track = 18;
-        let mut sector = 1;
-
-        loop {
-            let mut data = self.read_sector(dir_track, sector)?.to_vec();
+    fn write_dir_entry(&mut self, bam: &mut BAM, entry: [u8; 32]) -> Result<(), D64Error> {
+        let chain = self.directory_chain()?;
+        for &(track, sector) in &chain {
+            let mut data = self.read_sector(track, sector)?.to_vec();
             for i in (0..256).step_by(32) {
                 if data[i + 2] == 0 {
-                    data[i..i + 32].copy_from_slice(&entry);
-                    self.write_sector(dir_track, sector, &data)?;
-                    return Ok(());
+                    data[i + 2..i + 32].copy_from_slice(&entry[2..]);
+                    return self.write_sector(track, sector, &data);
                 }
             }
-            sector = data[1];
-            if sector == 0 {
-                return Err(D64Error::DiskFull);
-            }
         }
+
+        let dir_track = self.dir_track;
+        let (_, last_sector) = chain[chain.len() - 1];
+        let next_sector = bam.find_free_sector(dir_track).ok_or(D64Error::DiskFull)?;
+        bam.allocate_sector(dir_track, next_sector)?;
+        let mut data = self.read_sector(dir_track, last_sector)?.to_vec();
+        data[0] = dir_track;
+        data[1] = next_sector;
+        self.write_sector(dir_track, last_sector, &data)?;
+
+        let mut next_data = [0u8; 256];
+        next_data[1] = 0xFF;
+        next_data[2..32].copy_from_slice(&entry[2..]);
+        self.write_sector(dir_track, next_sector, &next_data)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct D64Builder {
+    tracks: u8,
+    disk_name: String,
+    disk_id: String,
+    files: Vec<(String, Vec<u8>, FileType)>,
+}
+
+impl Default for D64Builder {
+    fn default() -> Self {
+        Self {
+            tracks: 35,
+            disk_name: String::new(),
+            disk_id: String::from("00"),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl D64Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tracks(mut self, tracks: u8) -> Self {
+        self.tracks = tracks;
+        self
+    }
+
+    pub fn disk_name(mut self, name: &str) -> Self {
+        self.disk_name = name.to_string();
+        self
+    }
+
+    pub fn disk_id(mut self, id: &str) -> Self {
+        self.disk_id = id.to_string();
+        self
+    }
+
+    pub fn add_file(self, name: &str, content: &[u8]) -> Self {
+        self.add_file_typed(name, content, FileType::Prg)
+    }
+
+    pub fn add_file_typed(mut self, name: &str, content: &[u8], file_type: FileType) -> Self {
+        self.files
+            .push((name.to_string(), content.to_vec(), file_type));
+        self
+    }
+
+    pub fn build(self) -> Result<D64, D64Error> {
+        let mut d64 = D64::new(self.tracks)?;
+        d64.format(&self.disk_name, &self.disk_id)?;
+
+        let blocks_needed: usize = self
+            .files
+            .iter()
+            .map(|(_, content, _)| content.len().div_ceil(254).max(1))
+            .sum();
+        // The formatted directory holds eight entries; each further eight need
+        // another sector on the directory track, which data never uses.
+        let dir_sectors_needed = self.files.len().div_ceil(8).saturating_sub(1);
+        let dir_track_free = d64.read_bam()?.get_free_sectors_count(d64.dir_track)? as usize;
+        if blocks_needed > d64.free_blocks()? as usize || dir_sectors_needed > dir_track_free {
+            return Err(D64Error::DiskFull);
+        }
+
+        for (name, content, file_type) in &self.files {
+            d64.insert_file_typed(name, content, *file_type)?;
+        }
+        Ok(d64)
     }
 }
 
 impl BAM {
     fn from_sector_data(data: &[u8], tracks: u8) -> Result<Self, D64Error> {
+        if data.len() < 256 {
+            return Err(D64Error::InvalidSectorLength(data.len()));
+        }
         let mut bam = BAM {
             tracks,
-            free_sectors: [0; 40],
-            bitmap: [[0; 3]; 40],
+            free_sectors: [0; MAX_TRACKS as usize],
+            bitmap: [[0; 5]; MAX_TRACKS as usize],
             disk_name: [0; 16],
             disk_id: [0; 2],
             dos_type: data[2],
+            format_id: [data[165], data[166]],
+            layout: BamLayout::detect(data, tracks),
+            dir_track: dir_track_for(tracks),
         };
 
-        for track in 0..tracks as usize {
-            bam.free_sectors[track] = data[4 + track * 4];
-            bam.bitmap[track][0] = data[5 + track * 4];
-            bam.bitmap[track][1] = data[6 + track * 4];
-            bam.bitmap[track][2] = data[7 + track * 4];
+        for track in 0..bam.front_tracks() as usize {
+            let offset = bam.layout.entry_offset(track);
+            bam.free_sectors[track] = data[offset];
+            bam.bitmap[track][..3].copy_from_slice(&data[offset + 1..offset + 4]);
+        }
+
+        if tracks == D71_TRACKS {
+            for track in 35..D71_TRACKS as usize {
+                bam.free_sectors[track] = data[0xDD + track - 35];
+            }
         }
 
         bam.disk_name.copy_from_slice(&data[144..160]);
@@ -425,22 +2327,105 @@ impl BAM {
         data[1] = 1;
         data[2] = self.dos_type;
 
-        for track in 0..self.tracks as usize {
-            data[4 + track * 4] = self.free_sectors[track];
-            data[5 + track * 4] = self.bitmap[track][0];
-            data[6 + track * 4] = self.bitmap[track][1];
-            data[7 + track * 4] = self.bitmap[track][2];
+        for track in 0..self.front_tracks() as usize {
+            let offset = self.layout.entry_offset(track);
+            data[offset] = self.free_sectors[track];
+            data[offset + 1..offset + 4].copy_from_slice(&self.bitmap[track][..3]);
+        }
+
+        if self.tracks == D71_TRACKS {
+            data[3] = 0x80;
+            for track in 35..D71_TRACKS as usize {
+                data[0xDD + track - 35] = self.free_sectors[track];
+            }
         }
 
         data[144..160].copy_from_slice(&self.disk_name);
         data[162..164].copy_from_slice(&self.disk_id);
+        data[165..167].copy_from_slice(&self.format_id);
+
+        data
+    }
+
+    fn from_d81_sectors(header: &[u8], side_one: &[u8], side_two: &[u8]) -> Result<Self, D64Error> {
+        let mut bam = BAM {
+            tracks: D81_TRACKS,
+            free_sectors: [0; MAX_TRACKS as usize],
+            bitmap: [[0; 5]; MAX_TRACKS as usize],
+            disk_name: [0; 16],
+            disk_id: [0; 2],
+            dos_type: header[2],
+            format_id: [header[0x19], header[0x1A]],
+            layout: BamLayout::default(),
+            dir_track: dir_track_for(D81_TRACKS),
+        };
+
+        for (side, data) in [side_one, side_two].into_iter().enumerate() {
+            for i in 0..40 {
+                let track = side * 40 + i;
+                let offset = 0x10 + i * 6;
+                bam.free_sectors[track] = data[offset];
+                bam.bitmap[track].copy_from_slice(&data[offset + 1..offset + 6]);
+            }
+        }
 
+        bam.disk_name.copy_from_slice(&header[4..0x14]);
+        bam.disk_id.copy_from_slice(&header[0x16..0x18]);
+
+        Ok(bam)
+    }
+
+    fn to_d81_sector_data(&self, side: usize) -> Vec<u8> {
+        let mut data = vec![0; 256];
+        if side == 0 {
+            data[0] = 40;
+            data[1] = 2;
+        } else {
+            data[1] = 0xFF;
+        }
+        data[2] = self.dos_type;
+        data[3] = !self.dos_type;
+        data[4..6].copy_from_slice(&self.disk_id);
+        data[6] = 0xC0;
+
+        for i in 0..40 {
+            let track = side * 40 + i;
+            let offset = 0x10 + i * 6;
+            data[offset] = self.free_sectors[track];
+            data[offset + 1..offset + 6].copy_from_slice(&self.bitmap[track]);
+        }
+
+        data
+    }
+
+    fn front_tracks(&self) -> u8 {
+        if self.tracks == D71_TRACKS {
+            35
+        } else {
+            self.tracks
+        }
+    }
+
+    fn read_second_side(&mut self, data: &[u8]) {
+        for track in 35..D71_TRACKS as usize {
+            let offset = (track - 35) * 3;
+            self.bitmap[track][..3].copy_from_slice(&data[offset..offset + 3]);
+        }
+    }
+
+    fn to_second_side_data(&self) -> Vec<u8> {
+        let mut data = vec![0; 256];
+        for track in 35..D71_TRACKS as usize {
+            let offset = (track - 35) * 3;
+            data[offset..offset + 3].copy_from_slice(&self.bitmap[track][..3]);
+        }
         data
     }
 
     pub fn allocate_sector(&mut self, track: u8, sector: u8) -> Result<(), D64Error> {
-        if track == 0 || track > self.tracks || sector >= SECTORS_PER_TRACK[(track - 1) as usize] {
-            return Err(D64Error::InvalidTrackSector);
+        match sectors_per_track(self.tracks, track) {
+            Some(sectors) if sector < sectors => {}
+            _ => return Err(D64Error::InvalidTrackSector),
         }
 
         let track_idx = (track - 1) as usize;
@@ -452,14 +2437,19 @@ impl BAM {
         }
 
         self.bitmap[track_idx][byte_idx] &= !(1 << bit_idx);
-        self.free_sectors[track_idx] -= 1;
+        // A corrupt BAM can mark a sector free while its track count is zero;
+        // fall back to counting the bitmap instead of underflowing.
+        self.free_sectors[track_idx] = self.free_sectors[track_idx]
+            .checked_sub(1)
+            .unwrap_or_else(|| self.count_free_bits(track));
 
         Ok(())
     }
 
     pub fn free_sector(&mut self, track: u8, sector: u8) -> Result<(), D64Error> {
-        if track == 0 || track > self.tracks || sector >= SECTORS_PER_TRACK[(track - 1) as usize] {
-            return Err(D64Error::InvalidTrackSector);
+        match sectors_per_track(self.tracks, track) {
+            Some(sectors) if sector < sectors => {}
+            _ => return Err(D64Error::InvalidTrackSector),
         }
 
         let track_idx = (track - 1) as usize;
@@ -476,18 +2466,36 @@ impl BAM {
         Ok(())
     }
 
-    pub fn find_free_sector(&self, track: u8) -> Option<u8> {
-        if track == 0 || track > self.tracks {
-            return None;
+    pub fn reserve_directory(&mut self) -> Result<(), D64Error> {
+        let (track, sectors) = self.directory_sectors();
+        for sector in 0..sectors {
+            self.allocate_sector(track, sector)?;
+        }
+        Ok(())
+    }
+
+    pub fn release_directory(&mut self) -> Result<(), D64Error> {
+        let (track, sectors) = self.directory_sectors();
+        for sector in 0..sectors {
+            self.free_sector(track, sector)?;
         }
+        Ok(())
+    }
+
+    fn directory_sectors(&self) -> (u8, u8) {
+        let sectors = if self.tracks == D81_TRACKS { 4 } else { 2 };
+        (self.dir_track, sectors)
+    }
 
+    pub fn find_free_sector(&self, track: u8) -> Option<u8> {
+        let sectors = sectors_per_track(self.tracks, track)?;
         let track_idx = (track - 1) as usize;
         for (byte_idx, &byte) in self.bitmap[track_idx].iter().enumerate() {
             if byte != 0 {
                 for bit_idx in 0..8 {
                     if byte & (1 << bit_idx) != 0 {
                         let sector = (byte_idx as u8) * 8 + bit_idx;
-                        if sector < SECTORS_PER_TRACK[track_idx] {
+                        if sector < sectors {
                             return Some(sector);
                         }
                     }
@@ -497,6 +2505,84 @@ impl BAM {
         None
     }
 
+    fn first_free_sector(&self, allow_dir_track: bool) -> Result<(u8, u8), D64Error> {
+        for track in allocation_order(self.tracks, self.dir_track, allow_dir_track) {
+            if let Some(sector) = self.find_free_sector(track) {
+                return Ok((track, sector));
+            }
+        }
+        Err(D64Error::DiskFull)
+    }
+
+    fn next_free_sector(
+        &self,
+        track: u8,
+        sector: u8,
+        interleave: u8,
+    ) -> Result<(u8, u8), D64Error> {
+        let sectors = sectors_per_track(self.tracks, track).ok_or(D64Error::InvalidTrackSector)?;
+        let start = (sector as usize + interleave as usize) % sectors as usize;
+        for step in 0..sectors as usize {
+            let candidate = ((start + step) % sectors as usize) as u8;
+            if self.is_sector_free(track, candidate) {
+                return Ok((track, candidate));
+            }
+        }
+
+        let order = allocation_order(self.tracks, self.dir_track, false);
+        let position = order
+            .iter()
+            .position(|&candidate| candidate == track)
+            .map_or(0, |position| position + 1);
+        for &next_track in order[position..].iter().chain(&order[..position]) {
+            if let Some(sector) = self.find_free_sector(next_track) {
+                return Ok((next_track, sector));
+            }
+        }
+        Err(D64Error::DiskFull)
+    }
+
+    fn allocate_chain(
+        &mut self,
+        start: (u8, u8),
+        block_count: usize,
+        interleave: u8,
+    ) -> Result<Vec<(u8, u8)>, D64Error> {
+        self.allocate_sector(start.0, start.1)?;
+        let mut blocks = vec![start];
+        while blocks.len() < block_count {
+            let (track, sector) = blocks[blocks.len() - 1];
+            let next = self.next_free_sector(track, sector, interleave)?;
+            self.allocate_sector(next.0, next.1)?;
+            blocks.push(next);
+        }
+        Ok(blocks)
+    }
+
+    fn free_all(&mut self) {
+        for track in 1..=self.tracks {
+            let track_idx = (track - 1) as usize;
+            let sectors = sectors_per_track(self.tracks, track).unwrap_or(0);
+            self.bitmap[track_idx] = [0; 5];
+            for sector in 0..sectors {
+                self.bitmap[track_idx][(sector / 8) as usize] |= 1 << (sector % 8);
+            }
+            self.free_sectors[track_idx] = sectors;
+        }
+    }
+
+    pub fn is_allocated(&self, track: u8, sector: u8) -> Result<bool, D64Error> {
+        match sectors_per_track(self.tracks, track) {
+            Some(sectors) if sector < sectors => Ok(!self.is_sector_free(track, sector)),
+            _ => Err(D64Error::InvalidTrackSector),
+        }
+    }
+
+    fn is_sector_free(&self, track: u8, sector: u8) -> bool {
+        let track_idx = (track - 1) as usize;
+        self.bitmap[track_idx][(sector / 8) as usize] & (1 << (sector % 8)) != 0
+    }
+
     pub fn get_free_sectors_count(&self, track: u8) -> Result<u8, D64Error> {
         if track == 0 || track > self.tracks {
             return Err(D64Error::InvalidTrackSector);
@@ -504,22 +2590,85 @@ impl BAM {
         Ok(self.free_sectors[(track - 1) as usize])
     }
 
+    pub fn is_consistent(&self) -> bool {
+        (1..=self.tracks)
+            .all(|track| self.free_sectors[(track - 1) as usize] == self.count_free_bits(track))
+    }
+
+    pub fn recount_free(&mut self) {
+        for track in 1..=self.tracks {
+            self.free_sectors[(track - 1) as usize] = self.count_free_bits(track);
+        }
+    }
+
+    fn count_free_bits(&self, track: u8) -> u8 {
+        (0..sectors_per_track(self.tracks, track).unwrap_or(0))
+            .filter(|&sector| self.is_sector_free(track, sector))
+            .count() as u8
+    }
+
+    pub fn free_block_list(&self) -> Vec<(u8, u8)> {
+        allocation_order(self.tracks, self.dir_track, true)
+            .into_iter()
+            .flat_map(|track| {
+                (0..sectors_per_track(self.tracks, track).unwrap_or(0))
+                    .filter(move |&sector| self.is_sector_free(track, sector))
+                    .map(move |sector| (track, sector))
+            })
+            .collect()
+    }
+
+    pub fn total_free_sectors(&self) -> u16 {
+        self.free_sectors[..self.tracks as usize]
+            .iter()
+            .map(|&count| count as u16)
+            .sum()
+    }
+
     pub fn get_disk_name(&self) -> String {
-        petscii_to_ascii(&self.disk_name)
+        petscii_to_ascii(self.disk_name_trimmed())
+    }
+
+    pub fn disk_name_bytes(&self) -> &[u8; 16] {
+        &self.disk_name
+    }
+
+    pub fn disk_id_bytes(&self) -> &[u8; 2] {
+        &self.disk_id
+    }
+
+    pub fn disk_name_trimmed(&self) -> &[u8] {
+        let name_end = self.disk_name.iter().position(|&x| x == 0xA0).unwrap_or(16);
+        &self.disk_name[..name_end]
     }
 
     pub fn get_disk_id(&self) -> String {
         petscii_to_ascii(&self.disk_id)
     }
 
-    pub fn set_disk_name(&mut self, name: &str) {
-        let name_bytes = ascii_to_petscii(name);
-        self.disk_name[..name_bytes.len()].copy_from_slice(&name_bytes);
-        self.disk_name[name_bytes.len()..].fill(0xA0);
+    pub fn set_disk_name(&mut self, name: &str) -> Result<(), D64Error> {
+        self.disk_name = try_padded_petscii(name)?;
+        Ok(())
+    }
+
+    pub fn dos_type(&self) -> u8 {
+        self.dos_type
+    }
+
+    pub fn set_dos_type(&mut self, dos_type: u8) {
+        self.dos_type = dos_type;
+    }
+
+    pub fn get_format_id(&self) -> String {
+        petscii_to_ascii(&self.format_id)
+    }
+
+    pub fn set_format_id(&mut self, id: &str) {
+        self.format_id = padded_petscii(id);
     }
 
-    pub fn set_disk_id(&mut self, id: &str) {
-        let id_bytes = ascii_to_petscii(id);
-        self.disk_id.copy_from_slice(&id_bytes[..2]);
+    pub fn set_disk_id(&mut self, id: &str) -> Result<(), D64Error> {
+        self.disk_id = try_padded_petscii(id)?;
+        Ok(())
     }
 }
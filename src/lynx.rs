@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/lynx.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use std::io::Read;
+
+use crate::{entry_name, D64Error, FileType, D64};
+
+const BLOCK_SIZE: usize = 254;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LynxEntry {
+    pub name: String,
+    pub file_type: FileType,
+    pub data: Vec<u8>,
+}
+
+pub fn read_lynx<R: Read>(mut reader: R) -> Result<Vec<LynxEntry>, D64Error> {
+    let mut image = Vec::new();
+    reader.read_to_end(&mut image)?;
+
+    // The directory follows the BASIC loader stub, starting after the stub's
+    // terminating zero bytes and the carriage return that comes after them.
+    let signature = image
+        .windows(4)
+        .position(|window| window == b"LYNX")
+        .ok_or(D64Error::InvalidArchive)?;
+    let mut header_start = image[..signature]
+        .iter()
+        .rposition(|&byte| byte == 0x00)
+        .map_or(0, |pos| pos + 1);
+    while image[header_start] == 0x0D {
+        header_start += 1;
+    }
+
+    let mut fields = image[header_start..].split(|&byte| byte == 0x0D);
+    let directory_blocks = parse_number(next_field(&mut fields)?)?;
+    let file_count = parse_number(next_field(&mut fields)?)?;
+
+    let mut headers = Vec::new();
+    for _ in 0..file_count {
+        let name = entry_name(next_field(&mut fields)?);
+        let blocks = parse_number(next_field(&mut fields)?)?;
+        let file_type = match next_field(&mut fields)?.first() {
+            Some(b'D') => FileType::Del,
+            Some(b'S') => FileType::Seq,
+            Some(b'P') => FileType::Prg,
+            Some(b'U') => FileType::Usr,
+            Some(b'R') => {
+                next_field(&mut fields)?;
+                FileType::Rel
+            }
+            _ => return Err(D64Error::InvalidArchive),
+        };
+        let last_sector = parse_number(next_field(&mut fields)?)?;
+        headers.push((name, file_type, blocks, last_sector));
+    }
+
+    let mut offset = directory_blocks * BLOCK_SIZE;
+    let mut entries = Vec::new();
+    for (name, file_type, blocks, last_sector) in headers {
+        if offset > image.len() {
+            return Err(D64Error::InvalidArchive);
+        }
+        // The last-sector byte holds the count of bytes used plus one.
+        let len = (blocks.saturating_sub(1) * BLOCK_SIZE + last_sector.saturating_sub(1))
+            .min(image.len() - offset);
+        entries.push(LynxEntry {
+            name,
+            file_type,
+            data: image[offset..offset + len].to_vec(),
+        });
+        offset += blocks * BLOCK_SIZE;
+    }
+
+    Ok(entries)
+}
+
+fn next_field<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<&'a [u8], D64Error> {
+    fields.next().ok_or(D64Error::InvalidArchive)
+}
+
+fn parse_number(field: &[u8]) -> Result<usize, D64Error> {
+    let digits: String = field
+        .iter()
+        .map(|&byte| byte as char)
+        .skip_while(|c| *c == ' ')
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().map_err(|_| D64Error::InvalidArchive)
+}
+
+impl D64 {
+    pub fn import_lynx_entry(&mut self, entry: &LynxEntry) -> Result<(), D64Error> {
+        self.insert_file_typed(&entry.name, &entry.data, entry.file_type)
+    }
+}
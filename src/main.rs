@@ -4,10 +4,10 @@
 // Author: Volker Schwaberow <volker@schwaberow.de>
 // Copyright (c) 2024 Volker Schwaberow
 
-use std::{fs::File, io::Write};
+use std::{fs::File, path::Path};
 
-use clap::{Parser, Subcommand};
-use d64lib::{D64Error, D64};
+use clap::{Parser, Subcommand, ValueEnum};
+use d64lib::{basic, petscii_to_display, D64Error, Discrepancy, FileType, D64};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,6 +16,23 @@ struct Cli {
     command: Commands,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum InsertType {
+    Prg,
+    Seq,
+    Usr,
+}
+
+impl From<InsertType> for FileType {
+    fn from(file_type: InsertType) -> Self {
+        match file_type {
+            InsertType::Prg => FileType::Prg,
+            InsertType::Seq => FileType::Seq,
+            InsertType::Usr => FileType::Usr,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Read {
@@ -26,6 +43,14 @@ enum Commands {
         #[arg(short, long)]
         sector: u8,
     },
+    Dump {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short, long)]
+        track: u8,
+        #[arg(short, long)]
+        sector: u8,
+    },
     Write {
         #[arg(short, long)]
         file: String,
@@ -40,6 +65,8 @@ enum Commands {
     ShowBam {
         #[arg(short, long)]
         file: String,
+        #[arg(long)]
+        raw: bool,
     },
 
     FindFreeSector {
@@ -80,6 +107,8 @@ enum Commands {
     List {
         #[arg(short, long)]
         file: String,
+        #[arg(long)]
+        cbm: bool,
     },
     Extract {
         #[arg(short, long)]
@@ -89,11 +118,47 @@ enum Commands {
         #[arg(short, long)]
         output: String,
     },
+    Insert {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long)]
+        name: String,
+        #[arg(short = 't', long = "type", value_enum, default_value = "prg")]
+        file_type: InsertType,
+    },
+    ExtractAll {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short, long)]
+        output_dir: String,
+    },
+    Delete {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short = 'n', long)]
+        filename: String,
+        #[arg(long)]
+        force: bool,
+    },
+    Rename {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short, long)]
+        old: String,
+        #[arg(short, long)]
+        new: String,
+    },
     Create {
         #[arg(short, long)]
         file: String,
         #[arg(short, long, default_value = "35")]
         tracks: u8,
+        #[arg(short, long, default_value = "NEW DISK")]
+        name: String,
+        #[arg(short, long, default_value = "00")]
+        id: String,
     },
     Format {
         #[arg(short, long)]
@@ -103,12 +168,28 @@ enum Commands {
         #[arg(short, long)]
         id: String,
     },
+    Verify {
+        #[arg(short, long)]
+        file: String,
+    },
+    Copy {
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long)]
+        output: String,
+    },
     TraceFile {
         #[arg(short, long)]
         file: String,
         #[arg(short, long)]
         name: String,
     },
+    ListBasic {
+        #[arg(short, long)]
+        file: String,
+        #[arg(short = 'n', long)]
+        filename: String,
+    },
 }
 
 fn main() -> Result<(), D64Error> {
@@ -124,6 +205,14 @@ fn main() -> Result<(), D64Error> {
             let data = d64.read_sector(*track, *sector)?;
             println!("Sector data: {:?}", data);
         }
+        Commands::Dump {
+            file,
+            track,
+            sector,
+        } => {
+            let d64 = D64::from_file(file)?;
+            print!("{}", d64.hexdump_sector(*track, *sector)?);
+        }
         Commands::Write {
             file,
             track,
@@ -132,7 +221,15 @@ fn main() -> Result<(), D64Error> {
         } => {
             let mut d64 = D64::from_file(file)?;
             let bytes = hex::decode(data).map_err(|_| D64Error::InvalidTrackSector)?;
-            d64.write_sector(*track, *sector, &bytes)?;
+            match d64.write_sector(*track, *sector, &bytes) {
+                Err(D64Error::InvalidSectorLength(len)) => {
+                    eprintln!(
+                        "Sector data must be exactly 256 bytes (512 hex digits), got {len} bytes"
+                    );
+                    std::process::exit(1);
+                }
+                result => result?,
+            }
             d64.save_to_file(file)?;
             println!("Sector written successfully");
         }
@@ -147,6 +244,11 @@ fn main() -> Result<(), D64Error> {
                 Err(e) => return Err(e),
             }
         }
+        Commands::ListBasic { file, filename } => {
+            let d64 = D64::from_file(file)?;
+            let (load_addr, body) = d64.extract_prg(filename)?;
+            print!("{}", basic::detokenize(load_addr, &body)?);
+        }
         Commands::TraceFile { file, name } => {
             let d64 = D64::from_file(file)?;
             match d64.trace_file(name) {
@@ -166,7 +268,7 @@ fn main() -> Result<(), D64Error> {
         Commands::SetDiskName { file, name } => {
             let mut d64 = D64::from_file(file)?;
             let mut bam = d64.read_bam()?;
-            bam.set_disk_name(name);
+            bam.set_disk_name(name)?;
             d64.write_bam(&bam)?;
             d64.save_to_file(file)?;
             println!("Disk name set to: {}", name);
@@ -195,29 +297,45 @@ fn main() -> Result<(), D64Error> {
         Commands::SetDiskId { file, id } => {
             let mut d64 = D64::from_file(file)?;
             let mut bam = d64.read_bam()?;
-            bam.set_disk_id(id);
+            bam.set_disk_id(id)?;
             d64.write_bam(&bam)?;
             d64.save_to_file(file)?;
             println!("Disk ID set to: {}", id);
         }
 
-        Commands::ShowBam { file } => {
+        Commands::ShowBam { file, raw } => {
             let d64 = D64::from_file(file)?;
             let bam = d64.read_bam()?;
-            println!("Disk Name: {}", bam.get_disk_name());
+            println!("Disk Name: {}", petscii_to_display(bam.disk_name_trimmed()));
             println!("Disk ID: {}", bam.get_disk_id());
+            println!("DOS Type: ${:02X}", bam.dos_type());
+            println!("Format: {}", bam.get_format_id());
             println!("Free sectors per track:");
             for track in 1..=d64.tracks {
-                println!(
-                    "Track {}: {} free sectors",
-                    track,
-                    bam.get_free_sectors_count(track)?
-                );
+                let free = bam.get_free_sectors_count(track)?;
+                if *raw {
+                    let bytes = (d64.sectors_in_track(track)? as usize).div_ceil(8);
+                    println!(
+                        "Track {}: {} free sectors [{:02X}] {}",
+                        track,
+                        free,
+                        free,
+                        hex::encode_upper(&bam.bitmap[(track - 1) as usize][..bytes])
+                    );
+                } else {
+                    println!("Track {}: {} free sectors", track, free);
+                }
             }
+            println!("{} blocks free", d64.free_blocks()?);
         }
 
-        Commands::Create { file, tracks } => {
-            let d64 = D64::new(*tracks)?;
+        Commands::Create {
+            file,
+            tracks,
+            name,
+            id,
+        } => {
+            let d64 = D64::new_formatted(*tracks, name, id)?;
             d64.save_to_file(file)?;
             println!("Created new D64 file '{}' with {} tracks", file, tracks);
         }
@@ -230,13 +348,20 @@ fn main() -> Result<(), D64Error> {
                 file, name, id
             );
         }
-        Commands::List { file } => {
+        Commands::List { file, cbm: true } => {
+            let d64 = D64::from_file(file)?;
+            match d64.format_directory() {
+                Ok(listing) => print!("{}", listing),
+                Err(e) => println!("Error listing files: {}", e),
+            }
+        }
+        Commands::List { file, cbm: false } => {
             let d64 = D64::from_file(file)?;
-            match d64.list_files() {
-                Ok(files) => {
+            match d64.list_entries() {
+                Ok(entries) => {
                     println!("Files in {}:", file);
-                    for (i, file) in files.iter().enumerate() {
-                        println!("{:2}. {}", i + 1, file);
+                    for (i, entry) in entries.iter().enumerate() {
+                        println!("{:2}. {}", i + 1, entry.display_name());
                     }
                 }
                 Err(e) => println!("Error listing files: {}", e),
@@ -248,10 +373,79 @@ fn main() -> Result<(), D64Error> {
             output,
         } => {
             let d64 = D64::from_file(file)?;
-            let content = d64.extract_file(filename)?;
-            let mut output_file = File::create(output)?;
-            output_file.write_all(&content)?;
-            println!("File '{}' extracted to '{}'", filename, output);
+            if output == "-" {
+                d64.extract_file_to(filename, std::io::stdout().lock())?;
+            } else {
+                d64.extract_file_to(filename, File::create(output)?)?;
+                println!("File '{}' extracted to '{}'", filename, output);
+            }
+        }
+        Commands::Insert {
+            file,
+            input,
+            name,
+            file_type,
+        } => {
+            let mut d64 = D64::from_file(file)?;
+            d64.insert_host_file(Path::new(input), name, (*file_type).into())?;
+            d64.save_to_file(file)?;
+            println!("File '{}' inserted as '{}'", input, name);
+        }
+        Commands::ExtractAll { file, output_dir } => {
+            let d64 = D64::from_file(file)?;
+            for (name, path) in d64.extract_all(Path::new(output_dir))? {
+                println!("File '{}' extracted to '{}'", name, path.display());
+            }
+        }
+        Commands::Verify { file } => {
+            let d64 = D64::from_file(file)?;
+            let report = d64.validate()?;
+            for (track, sector, discrepancy) in &report.discrepancies {
+                let problem = match discrepancy {
+                    Discrepancy::AllocatedButUnused => "allocated in BAM but not used",
+                    Discrepancy::UsedButFree => "used by a file but free in BAM",
+                    Discrepancy::CrossLinked => "used by more than one file",
+                };
+                println!("Track {}, Sector {}: {}", track, sector, problem);
+            }
+            let consistent = d64.read_bam()?.is_consistent();
+            if !consistent {
+                println!("BAM free-sector counts do not match the bitmap");
+            }
+            if !report.is_valid() || !consistent {
+                eprintln!("'{}' failed verification", file);
+                std::process::exit(1);
+            }
+            println!("'{}' verified OK", file);
+        }
+        Commands::Copy { input, output } => {
+            let d64 = D64::open_read_only(input)?;
+            d64.clone_to_file(output)?;
+            let copy = D64::from_file(output)?;
+            if copy.tracks != d64.tracks || copy.content_hash() != d64.content_hash() {
+                eprintln!("Copy '{}' does not match '{}'", output, input);
+                std::process::exit(1);
+            }
+            println!(
+                "Copied {}-track image '{}' to '{}'",
+                d64.tracks, input, output
+            );
+        }
+        Commands::Delete {
+            file,
+            filename,
+            force,
+        } => {
+            let mut d64 = D64::from_file(file)?;
+            d64.delete_file_with(filename, *force)?;
+            d64.save_to_file(file)?;
+            println!("File '{}' deleted", filename);
+        }
+        Commands::Rename { file, old, new } => {
+            let mut d64 = D64::from_file(file)?;
+            d64.rename_file(old, new)?;
+            d64.save_to_file(file)?;
+            println!("File '{}' renamed to '{}'", old, new);
         }
     }
 
@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/pc64.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use std::io::{Read, Write};
+
+use crate::{ascii_to_petscii, petscii_to_ascii, D64Error, FileType, D64};
+
+const SIGNATURE: &[u8; 8] = b"C64File\0";
+const HEADER_SIZE: usize = 26;
+
+pub fn read_p00<R: Read>(mut reader: R) -> Result<(String, u8, Vec<u8>), D64Error> {
+    let mut image = Vec::new();
+    reader.read_to_end(&mut image)?;
+
+    if image.len() < HEADER_SIZE || !image.starts_with(SIGNATURE) {
+        return Err(D64Error::InvalidArchive);
+    }
+
+    let name = &image[8..24];
+    let name_end = name.iter().position(|&c| c == 0x00).unwrap_or(name.len());
+    Ok((
+        petscii_to_ascii(&name[..name_end]),
+        image[25],
+        image[HEADER_SIZE..].to_vec(),
+    ))
+}
+
+pub fn write_p00<W: Write>(mut writer: W, name: &str, data: &[u8]) -> Result<(), D64Error> {
+    let name_bytes = ascii_to_petscii(name);
+    if name_bytes.len() > 16 {
+        return Err(D64Error::NameTooLong);
+    }
+
+    let mut header = [0u8; HEADER_SIZE];
+    header[..8].copy_from_slice(SIGNATURE);
+    header[8..8 + name_bytes.len()].copy_from_slice(&name_bytes);
+    writer.write_all(&header)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+impl D64 {
+    pub fn import_p00<R: Read>(&mut self, reader: R, file_type: FileType) -> Result<(), D64Error> {
+        let (name, _, data) = read_p00(reader)?;
+        self.insert_file_typed(&name, &data, file_type)
+    }
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/rel.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use std::collections::HashSet;
+
+use crate::{block_content, D64Error, FileType, D64};
+
+const SIDE_SECTOR_POINTERS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelFile {
+    pub record_length: u8,
+    data: Vec<u8>,
+}
+
+impl RelFile {
+    pub fn record_count(&self) -> usize {
+        self.data.len() / self.record_length as usize
+    }
+
+    pub fn record(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.record_count() {
+            return None;
+        }
+        let start = index * self.record_length as usize;
+        Some(&self.data[start..start + self.record_length as usize])
+    }
+}
+
+impl D64 {
+    pub fn read_rel(&self, filename: &str) -> Result<RelFile, D64Error> {
+        let (dir_sector, offset) = self.find_dir_entry(filename)?;
+        let slot = &self.read_sector(self.dir_track, dir_sector)?[offset..offset + 32];
+        let record_length = slot[23];
+        if FileType::from_byte(slot[2]) != Some(FileType::Rel) || record_length == 0 {
+            return Err(D64Error::NotRelFile);
+        }
+
+        let mut data = Vec::new();
+        for (track, sector) in self.rel_data_blocks(slot[21], slot[22])? {
+            data.extend_from_slice(block_content(self.read_sector(track, sector)?)?);
+        }
+
+        Ok(RelFile {
+            record_length,
+            data,
+        })
+    }
+
+    fn rel_data_blocks(&self, mut track: u8, mut sector: u8) -> Result<Vec<(u8, u8)>, D64Error> {
+        let mut blocks = Vec::new();
+        let mut visited = HashSet::new();
+
+        while track != 0 {
+            if !visited.insert((track, sector)) {
+                return Err(D64Error::InvalidTrackSector);
+            }
+            let side_sector = self.read_sector(track, sector)?;
+            for pointer in side_sector[SIDE_SECTOR_POINTERS..].chunks(2) {
+                if pointer[0] == 0 {
+                    break;
+                }
+                blocks.push((pointer[0], pointer[1]));
+            }
+            track = side_sector[0];
+            sector = side_sector[1];
+        }
+
+        Ok(blocks)
+    }
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/serde_support.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+pub(crate) mod petscii_text {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::{entry_name, padded_petscii};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&entry_name(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(padded_petscii(&text))
+    }
+}
+
+pub(crate) mod track_counts {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        counts: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(counts)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let counts = Vec::<u8>::deserialize(deserializer)?;
+        let len = counts.len();
+        counts
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"one entry per track"))
+    }
+}
+
+pub(crate) mod track_bitmaps {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bitmaps: &[[u8; 5]; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(bitmaps.iter().map(hex::encode_upper))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[[u8; 5]; N], D::Error> {
+        let encoded = Vec::<String>::deserialize(deserializer)?;
+        if encoded.len() != N {
+            return Err(D::Error::invalid_length(
+                encoded.len(),
+                &"one bitmap per track",
+            ));
+        }
+
+        let mut bitmaps = [[0u8; 5]; N];
+        for (bitmap, text) in bitmaps.iter_mut().zip(&encoded) {
+            hex::decode_to_slice(text, bitmap).map_err(D::Error::custom)?;
+        }
+        Ok(bitmaps)
+    }
+}
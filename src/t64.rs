@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+// Project: dtools
+// File: src/t64.rs
+// Author: Volker Schwaberow <volker@schwaberow.de>
+// Copyright (c) 2024 Volker Schwaberow
+
+use std::io::Read;
+
+use crate::{petscii_to_ascii, D64Error, D64};
+
+const HEADER_SIZE: usize = 64;
+const RECORD_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct T64Entry {
+    pub name: String,
+    pub load_address: u16,
+    pub data: Vec<u8>,
+}
+
+pub fn read_t64<R: Read>(mut reader: R) -> Result<Vec<T64Entry>, D64Error> {
+    let mut image = Vec::new();
+    reader.read_to_end(&mut image)?;
+
+    if image.len() < HEADER_SIZE || !image.starts_with(b"C64") {
+        return Err(D64Error::InvalidArchive);
+    }
+
+    let max_entries = u16::from_le_bytes([image[0x22], image[0x23]]) as usize;
+    let records_end = HEADER_SIZE + max_entries * RECORD_SIZE;
+    if image.len() < records_end {
+        return Err(D64Error::InvalidArchive);
+    }
+
+    let mut records = Vec::new();
+    for record in image[HEADER_SIZE..records_end].chunks(RECORD_SIZE) {
+        if record[0] == 0 {
+            continue;
+        }
+        let start = u16::from_le_bytes([record[2], record[3]]);
+        let end = u16::from_le_bytes([record[4], record[5]]);
+        let offset = u32::from_le_bytes([record[8], record[9], record[10], record[11]]) as usize;
+        if offset > image.len() {
+            return Err(D64Error::InvalidArchive);
+        }
+        records.push((offset, start, end, &record[16..32]));
+    }
+
+    let mut offsets: Vec<usize> = records.iter().map(|record| record.0).collect();
+    offsets.push(image.len());
+    offsets.sort_unstable();
+
+    let mut entries = Vec::new();
+    for (offset, start, end, name) in records {
+        // Many T64 writers store a bogus end address, so never read past the
+        // next entry's data or the end of the file.
+        let limit = offsets
+            .iter()
+            .copied()
+            .find(|&next| next > offset)
+            .unwrap_or(image.len());
+        let len = (end.wrapping_sub(start) as usize).min(limit - offset);
+
+        let name_end = name
+            .iter()
+            .rposition(|&c| c != 0x20 && c != 0xA0 && c != 0x00)
+            .map_or(0, |pos| pos + 1);
+
+        entries.push(T64Entry {
+            name: petscii_to_ascii(&name[..name_end]),
+            load_address: start,
+            data: image[offset..offset + len].to_vec(),
+        });
+    }
+
+    Ok(entries)
+}
+
+impl D64 {
+    pub fn import_t64_entry(&mut self, entry: &T64Entry) -> Result<(), D64Error> {
+        self.import_prg(&entry.name, entry.load_address, &entry.data)
+    }
+}
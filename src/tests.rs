@@ -5,15 +5,26 @@
 // Copyright (c) 2024 Volker Schwaberow
 
 use super::*;
+use std::cell::RefCell;
 use std::io::Cursor;
 
+thread_local! {
+    // Every sector written on this test's thread, in order.
+    pub(crate) static SECTOR_WRITES: RefCell<Vec<(u8, u8)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn take_sector_writes() -> Vec<(u8, u8)> {
+    SECTOR_WRITES.with(|writes| writes.take())
+}
+
 fn create_mock_d64() -> D64 {
     let mut d64 = D64::new(35).unwrap();
-    
+    d64.format("TEST DISK", "2A").unwrap();
+
     // Create a simple file system structure
     let mut bam = d64.read_bam().unwrap();
-    bam.set_disk_name("TEST DISK");
-    bam.set_disk_id("2A");
+    bam.set_disk_name("TEST DISK").unwrap();
+    bam.set_disk_id("2A").unwrap();
     d64.write_bam(&bam).unwrap();
 
     // Add a file
@@ -30,6 +41,26 @@ fn test_new_d64() {
     assert_eq!(d64.data.len(), D64_35_TRACKS_SIZE);
 }
 
+#[test]
+fn test_new_formatted() {
+    let d64 = D64::new_formatted(35, "FRESH", "01").unwrap();
+    assert!(d64.list_files().unwrap().is_empty());
+    assert_eq!(d64.free_blocks().unwrap(), 664);
+    assert_eq!(d64.read_bam().unwrap().get_disk_name(), "FRESH");
+
+    assert_eq!(
+        D64::new_formatted(80, "FRESH", "01")
+            .unwrap()
+            .free_blocks()
+            .unwrap(),
+        3160
+    );
+    assert!(matches!(
+        D64::new_formatted(36, "FRESH", "01"),
+        Err(D64Error::InvalidFileSize)
+    ));
+}
+
 #[test]
 fn test_read_write_sector() {
     let mut d64 = create_mock_d64();
@@ -41,9 +72,9 @@ fn test_read_write_sector() {
 
 #[test]
 fn test_bam_operations() {
-    let mut d64 = create_mock_d64();
+    let d64 = create_mock_d64();
     let mut bam = d64.read_bam().unwrap();
-    
+
     assert_eq!(bam.get_disk_name(), "TEST DISK");
     assert_eq!(bam.get_disk_id(), "2A");
 
@@ -57,7 +88,7 @@ fn test_bam_operations() {
 #[test]
 fn test_file_operations() {
     let mut d64 = create_mock_d64();
-    
+
     let files = d64.list_files().unwrap();
     assert_eq!(files.len(), 1);
     assert_eq!(files[0], "TEST FILE");
@@ -72,32 +103,2376 @@ fn test_file_operations() {
 }
 
 #[test]
-fn test_find_free_sector() {
+fn test_insert_file_allocates_sectors() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("TEST DISK", "2A").unwrap();
+    let before = d64.read_bam().unwrap().get_free_sectors_count(17).unwrap();
+
+    d64.insert_file("FIRST", &[0x11; 300]).unwrap();
+    d64.insert_file("SECOND", &[0x22; 10]).unwrap();
+
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(17).unwrap(), before - 3);
+
+    assert_eq!(d64.read_sector(17, 0).unwrap()[..4], [17, 10, 0x11, 0x11]);
+    assert_eq!(d64.read_sector(17, 10).unwrap()[..3], [0, 47, 0x11]);
+    assert_eq!(d64.read_sector(17, 1).unwrap()[..3], [0, 11, 0x22]);
+}
+
+#[test]
+fn test_allocate_with_zero_free_count() {
     let mut d64 = create_mock_d64();
-    let (track, sector) = d64.find_free_sector().unwrap();
-    assert!(track > 0 && track <= 35);
-    assert!(sector < SECTORS_PER_TRACK[(track - 1) as usize]);
+    let mut bam = d64.read_bam().unwrap();
+    bam.free_sectors[16] = 0;
+    d64.write_bam(&bam).unwrap();
+
+    d64.insert_file("SECOND", &[0x22; 10]).unwrap();
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(17).unwrap(), 21 - 2);
+    assert!(bam.is_consistent());
 }
 
 #[test]
-fn test_trace_file() {
+fn test_delete_file() {
+    let mut d64 = create_mock_d64();
+    let before = d64.read_bam().unwrap().get_free_sectors_count(17).unwrap();
+
+    d64.insert_file("DOOMED PROGRAM 1", &[0x33; 600]).unwrap();
+    assert_eq!(
+        d64.read_bam().unwrap().get_free_sectors_count(17).unwrap(),
+        before - 3
+    );
+
+    d64.delete_file("DOOMED PROGRAM 1").unwrap();
+    assert!(!d64
+        .list_files()
+        .unwrap()
+        .contains(&"DOOMED PROGRAM 1".to_string()));
+    assert_eq!(
+        d64.read_bam().unwrap().get_free_sectors_count(17).unwrap(),
+        before
+    );
+    assert!(matches!(
+        d64.delete_file("DOOMED PROGRAM 1"),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_rename_file() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("ORIGINAL PROGRAM", b"payload").unwrap();
+    d64.rename_file("ORIGINAL PROGRAM", "RENAMED PROGRAM!")
+        .unwrap();
+
+    let files = d64.list_files().unwrap();
+    assert!(files.contains(&"RENAMED PROGRAM!".to_string()));
+    assert!(!files.contains(&"ORIGINAL PROGRAM".to_string()));
+    assert_eq!(d64.extract_file("RENAMED PROGRAM!").unwrap(), b"payload");
+
+    assert!(matches!(
+        d64.rename_file("RENAMED PROGRAM!", "THIS NAME IS TOO LONG"),
+        Err(D64Error::NameTooLong)
+    ));
+    assert!(matches!(
+        d64.rename_file("MISSING", "OTHER"),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_find_file_with_padded_name() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("HI", b"padded").unwrap();
+
+    // Pad the name the way a real 1541 directory stores it.
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    dir[32 + 7..32 + 21].fill(0xA0);
+    d64.write_sector(18, 1, &dir).unwrap();
+
+    assert_eq!(d64.extract_file("HI").unwrap(), b"padded");
+    assert_eq!(d64.trace_file("HI").unwrap().len(), 1);
+}
+
+#[test]
+fn test_dir_entry_name_padding() {
     let d64 = create_mock_d64();
-    let sectors = d64.trace_file("TEST FILE").unwrap();
-    assert!(!sectors.is_empty());
-    assert_eq!(sectors[0].0, 18); // First sector should be on track 18 (directory track)
+    let dir = d64.read_sector(18, 1).unwrap();
+    assert_eq!(&dir[5..14], &ascii_to_petscii("TEST FILE")[..]);
+    assert!(dir[14..21].iter().all(|&b| b == 0xA0));
 }
 
 #[test]
-#[should_panic(expected = "InvalidTrackSector")]
-fn test_invalid_sector_access() {
+fn test_insert_file_typed() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file_typed("LOG", b"data", FileType::Seq)
+        .unwrap();
+
+    let dir = d64.read_sector(18, 1).unwrap();
+    assert_eq!(dir[2], 0x82);
+    assert_eq!(dir[32 + 2], 0x81);
+    assert_eq!(FileType::from_byte(dir[32 + 2]), Some(FileType::Seq));
+    assert_eq!(FileType::Seq.to_string(), "SEQ");
+    assert_eq!(d64.extract_file("LOG").unwrap(), b"data");
+}
+
+#[test]
+fn test_insert_file_typed_rejects_del() {
+    let mut d64 = create_mock_d64();
+    let free_before = d64.free_blocks().unwrap();
+    assert!(matches!(
+        d64.insert_file_typed("GONE", b"data", FileType::Del),
+        Err(D64Error::UnsupportedFileType(FileType::Del))
+    ));
+    assert_eq!(d64.free_blocks().unwrap(), free_before);
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+}
+
+#[test]
+fn test_insert_file_typed_rejects_rel() {
+    let mut d64 = create_mock_d64();
+    let free_before = d64.free_blocks().unwrap();
+    assert!(matches!(
+        d64.insert_file_typed("RECORDS", &[0; 10], FileType::Rel),
+        Err(D64Error::UnsupportedFileType(FileType::Rel))
+    ));
+    assert_eq!(d64.free_blocks().unwrap(), free_before);
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+}
+
+#[test]
+fn test_list_entries() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file_typed("NOTES", b"text", FileType::Seq)
+        .unwrap();
+
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    dir[30] = 1;
+    d64.write_sector(18, 1, &dir).unwrap();
+
+    let entries = d64.list_entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "TEST FILE");
+    assert_eq!(entries[0].file_type, FileType::Prg);
+    assert_eq!(entries[0].size_blocks, 1);
+    assert_eq!(
+        (entries[0].start_track, entries[0].start_sector),
+        d64.find_file("TEST FILE").unwrap()
+    );
+    assert_eq!(entries[1].name, "NOTES");
+    assert_eq!(entries[1].file_type, FileType::Seq);
+}
+
+#[test]
+fn test_format_directory() {
     let d64 = create_mock_d64();
-    d64.read_sector(0, 0).unwrap(); // Track 0 doesn't exist
+    let listing = d64.format_directory().unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+
+    let bam = d64.read_bam().unwrap();
+    let blocks_free: u16 = (1..=35)
+        .filter(|&track| track != 18)
+        .map(|track| bam.get_free_sectors_count(track).unwrap() as u16)
+        .sum();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "0 \"TEST DISK       \" 2A 2A");
+    assert_eq!(lines[1], "1    \"TEST FILE\"        PRG");
+    assert_eq!(lines[2], format!("{} BLOCKS FREE.", blocks_free));
 }
 
 #[test]
-fn test_petscii_conversion() {
-    let ascii = "HELLO, WORLD!";
-    let petscii = ascii_to_petscii(ascii);
-    let back_to_ascii = petscii_to_ascii(&petscii);
-    assert_eq!(ascii, back_to_ascii);
-}
\ No newline at end of file
+fn test_d71_round_trip() {
+    let mut d71 = D64::new(70).unwrap();
+    assert_eq!(d71.data.len(), 349696);
+    d71.format("DOUBLE SIDED", "71").unwrap();
+
+    let bam = d71.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(36).unwrap(), 21);
+    assert_eq!(bam.get_free_sectors_count(53).unwrap(), 0);
+    assert_eq!(bam.get_free_sectors_count(70).unwrap(), 17);
+
+    let content: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+    d71.insert_file("BIG FILE", &content).unwrap();
+    assert_eq!(d71.extract_file("BIG FILE").unwrap(), content);
+
+    d71.allocate_sector(40, 3).unwrap();
+    let bam = d71.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(40).unwrap(), 20);
+    assert_eq!(bam.find_free_sector(40), Some(0));
+
+    let last = D71_70_TRACKS_SIZE - 256;
+    d71.write_sector(70, 16, &[0x5A; 256]).unwrap();
+    assert!(d71.data[last..].iter().all(|&b| b == 0x5A));
+}
+
+#[test]
+fn test_d81_round_trip() {
+    let mut d81 = D64::new(80).unwrap();
+    assert_eq!(d81.data.len(), 819200);
+    d81.format("FLOPPY", "81").unwrap();
+
+    let header = d81.read_sector(40, 0).unwrap();
+    assert_eq!(&header[0x19..0x1B], b"3D");
+    assert_eq!(d81.read_sector(40, 1).unwrap()[..4], [40, 2, 0x44, 0xBB]);
+
+    let bam = d81.read_bam().unwrap();
+    assert_eq!(bam.get_disk_name(), "FLOPPY");
+    assert_eq!(bam.get_free_sectors_count(1).unwrap(), 40);
+    assert_eq!(bam.get_free_sectors_count(40).unwrap(), 36);
+    assert_eq!(bam.get_free_sectors_count(80).unwrap(), 40);
+    assert!(d81.list_files().unwrap().is_empty());
+
+    let content: Vec<u8> = (0..12000).map(|i| (i % 251) as u8).collect();
+    d81.insert_file("PROGRAM", &content).unwrap();
+    assert_eq!(d81.list_files().unwrap(), vec!["PROGRAM".to_string()]);
+    assert_eq!(d81.extract_file("PROGRAM").unwrap(), content);
+    assert_eq!(d81.trace_file("PROGRAM").unwrap().len(), 48);
+    let bam = d81.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(39).unwrap(), 0);
+    assert_eq!(bam.get_free_sectors_count(41).unwrap(), 32);
+}
+
+#[test]
+fn test_reader_writer_round_trip() {
+    let d64 = create_mock_d64();
+    let mut buffer = Vec::new();
+    d64.to_writer(&mut buffer).unwrap();
+    assert_eq!(buffer.len(), D64_35_TRACKS_SIZE);
+
+    let loaded = D64::from_reader(Cursor::new(buffer)).unwrap();
+    assert_eq!(loaded.tracks, 35);
+    assert_eq!(loaded.data, d64.data);
+    assert_eq!(loaded.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+
+    assert!(matches!(
+        D64::from_reader(Cursor::new(vec![0u8; 100])),
+        Err(D64Error::InvalidFileSize)
+    ));
+}
+
+#[test]
+fn test_validate() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SECOND", &[0x44; 300]).unwrap();
+    let baseline = d64.validate().unwrap();
+    assert!(!baseline
+        .discrepancies
+        .iter()
+        .any(|&(_, _, kind)| kind != Discrepancy::AllocatedButUnused));
+
+    let second = d64.trace_file("SECOND").unwrap();
+    d64.free_sector(second[1].0, second[1].1).unwrap();
+    d64.allocate_sector(1, 10).unwrap();
+
+    // Point TEST FILE at the same chain as SECOND.
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    dir[3] = second[0].0;
+    dir[4] = second[0].1;
+    d64.write_sector(18, 1, &dir).unwrap();
+
+    let report = d64.validate().unwrap();
+    assert!(report
+        .discrepancies
+        .contains(&(second[1].0, second[1].1, Discrepancy::UsedButFree)));
+    assert!(report
+        .discrepancies
+        .contains(&(1, 10, Discrepancy::AllocatedButUnused)));
+    assert!(report
+        .discrepancies
+        .contains(&(second[0].0, second[0].1, Discrepancy::CrossLinked)));
+    assert!(!report.is_valid());
+}
+
+#[test]
+fn test_rebuild_bam() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SECOND", &[0x55; 600]).unwrap();
+
+    let mut bam = d64.read_bam().unwrap();
+    bam.free_sectors[16] = 5;
+    d64.write_bam(&bam).unwrap();
+
+    d64.rebuild_bam().unwrap();
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(1).unwrap(), 21);
+    assert_eq!(bam.get_free_sectors_count(17).unwrap(), 21 - 4);
+    assert_eq!(bam.get_free_sectors_count(18).unwrap(), 19 - 2);
+    assert_eq!(bam.get_free_sectors_count(19).unwrap(), 19);
+    assert_eq!(bam.get_disk_name(), "TEST DISK");
+    assert!(d64.validate().unwrap().is_valid());
+}
+
+#[test]
+fn test_rebuild_bam_keeps_rel_side_sectors() {
+    let mut d64 = create_mock_d64();
+    let records: Vec<u8> = (0..30u8).flat_map(|i| [i; 10]).collect();
+    d64.insert_file("RECORDS", &records).unwrap();
+    let blocks = d64.trace_file("RECORDS").unwrap();
+
+    let mut side_sector = vec![0u8; 256];
+    side_sector[1] = 0x11;
+    side_sector[3] = 10;
+    side_sector[4..6].copy_from_slice(&[20, 0]);
+    for (i, (track, sector)) in blocks.iter().enumerate() {
+        side_sector[16 + i * 2] = *track;
+        side_sector[17 + i * 2] = *sector;
+    }
+    d64.write_sector(20, 0, &side_sector).unwrap();
+    let (dir_sector, offset) = d64.find_dir_entry("RECORDS").unwrap();
+    let mut dir = d64.read_sector(18, dir_sector).unwrap().to_vec();
+    dir[offset + 2] = 0x84;
+    dir[offset + 21] = 20;
+    dir[offset + 22] = 0;
+    dir[offset + 23] = 10;
+    d64.write_sector(18, dir_sector, &dir).unwrap();
+
+    d64.rebuild_bam().unwrap();
+    assert!(d64.is_allocated(20, 0).unwrap());
+    assert!(d64.validate().unwrap().is_valid());
+
+    d64.insert_file("FILLER", &[0x77; 254 * 120]).unwrap();
+    assert_eq!(d64.read_sector(20, 0).unwrap(), side_sector.as_slice());
+    assert_eq!(d64.read_rel("RECORDS").unwrap().record_count(), 30);
+}
+
+#[test]
+fn test_long_disk_name_and_short_id() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("A VERY LONG DISK NAME", "X").unwrap();
+    let mut bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_disk_name(), "A VERY LONG DISK");
+    assert_eq!(bam.disk_id, [b'X', 0xA0]);
+
+    bam.set_disk_name("ANOTHER VERY LONG NAME").unwrap();
+    bam.set_disk_id("Z").unwrap();
+    assert_eq!(bam.get_disk_name(), "ANOTHER VERY LON");
+    assert_eq!(bam.disk_id, [b'Z', 0xA0]);
+}
+
+#[test]
+fn test_insert_file_interleave() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SKEWED", &[0x66; 1000]).unwrap();
+    assert_eq!(
+        d64.trace_file("SKEWED").unwrap(),
+        vec![(17, 1), (17, 11), (17, 2), (17, 12)]
+    );
+
+    d64.insert_file_with_interleave("TIGHT", &[0x77; 1000], 3)
+        .unwrap();
+    assert_eq!(
+        d64.trace_file("TIGHT").unwrap(),
+        vec![(17, 3), (17, 6), (17, 9), (17, 13)]
+    );
+    assert_eq!(d64.extract_file("TIGHT").unwrap(), vec![0x77; 1000]);
+}
+
+#[test]
+fn test_interleave_policies() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file_with_policy("STANDARD", &[0x11; 1000], InterleavePolicy::Standard)
+        .unwrap();
+    assert_eq!(
+        d64.trace_file("STANDARD").unwrap(),
+        vec![(17, 1), (17, 11), (17, 2), (17, 12)]
+    );
+
+    d64.insert_file_with_policy("FAST", &[0x22; 1000], InterleavePolicy::Contiguous)
+        .unwrap();
+    assert_eq!(
+        d64.trace_file("FAST").unwrap(),
+        vec![(17, 3), (17, 4), (17, 5), (17, 6)]
+    );
+
+    d64.insert_file_with_policy("CUSTOM", &[0x33; 1000], InterleavePolicy::Custom(5))
+        .unwrap();
+    assert_eq!(
+        d64.trace_file("CUSTOM").unwrap(),
+        vec![(17, 7), (17, 13), (17, 18), (17, 8)]
+    );
+    assert_eq!(d64.extract_file("FAST").unwrap(), vec![0x22; 1000]);
+}
+
+#[test]
+fn test_prg_load_address() {
+    let mut d64 = create_mock_d64();
+    d64.import_prg("LOADER", 0xC000, &[0xA9, 0x00, 0x60])
+        .unwrap();
+    assert_eq!(
+        d64.extract_file("LOADER").unwrap(),
+        vec![0x00, 0xC0, 0xA9, 0x00, 0x60]
+    );
+    assert_eq!(
+        d64.extract_prg("LOADER").unwrap(),
+        (0xC000, vec![0xA9, 0x00, 0x60])
+    );
+
+    d64.insert_file("TINY", &[0x01]).unwrap();
+    assert!(matches!(
+        d64.extract_prg("TINY"),
+        Err(D64Error::FileTooShort)
+    ));
+}
+
+#[test]
+fn test_full_last_sector() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("FULL", &[0x55; 254]).unwrap();
+    let (track, sector) = d64.find_file("FULL").unwrap();
+    assert_eq!(d64.read_sector(track, sector).unwrap()[..2], [0, 0xFF]);
+    assert_eq!(d64.extract_file("FULL").unwrap(), vec![0x55; 254]);
+}
+
+#[test]
+fn test_extract_file_rejects_bad_last_sector_count() {
+    let mut d64 = create_mock_d64();
+    let (track, sector) = d64.find_file("TEST FILE").unwrap();
+    let mut data = d64.read_sector(track, sector).unwrap().to_vec();
+    data[0] = 0x00;
+    data[1] = 0x00;
+    d64.write_sector(track, sector, &data).unwrap();
+
+    assert!(matches!(
+        d64.extract_file("TEST FILE"),
+        Err(D64Error::InvalidTrackSector)
+    ));
+}
+
+#[test]
+fn test_cyclic_chain_is_rejected() {
+    let mut d64 = create_mock_d64();
+    let (track, sector) = d64.find_file("TEST FILE").unwrap();
+    let mut data = d64.read_sector(track, sector).unwrap().to_vec();
+    data[0] = track;
+    data[1] = sector;
+    d64.write_sector(track, sector, &data).unwrap();
+
+    assert!(matches!(
+        d64.trace_file("TEST FILE"),
+        Err(D64Error::InvalidTrackSector)
+    ));
+    assert!(matches!(
+        d64.extract_file("TEST FILE"),
+        Err(D64Error::InvalidTrackSector)
+    ));
+
+    let mut d64 = create_mock_d64();
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    dir[0] = 18;
+    dir[1] = 4;
+    d64.write_sector(18, 1, &dir).unwrap();
+    let mut next = [0u8; 256];
+    next[0] = 18;
+    next[1] = 4;
+    d64.write_sector(18, 4, &next).unwrap();
+
+    assert!(matches!(
+        d64.extract_file("NOPE"),
+        Err(D64Error::InvalidTrackSector)
+    ));
+    assert!(matches!(
+        d64.insert_file("NEW", b"data"),
+        Err(D64Error::InvalidTrackSector)
+    ));
+
+    dir[1] = 1;
+    d64.write_sector(18, 1, &dir).unwrap();
+    assert!(matches!(
+        d64.extract_file("NOPE"),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_hexdump_sector() {
+    let d64 = create_mock_d64();
+    let dump = d64.hexdump_sector(18, 1).unwrap();
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 16);
+    assert_eq!(
+        lines[0],
+        "00: 00 FF 82 11 00 54 45 53 54 20 46 49 4C 45 A0 A0  |?????TEST FILE??|"
+    );
+    assert!(lines[15].starts_with("F0: "));
+}
+
+#[test]
+fn test_error_info_image() {
+    let mut image = create_mock_d64().data;
+    let mut errors = vec![0x01; 683];
+    errors[357] = 0x05;
+    image.extend_from_slice(&errors);
+    assert_eq!(image.len(), 175531);
+
+    let d64 = D64::from_reader(Cursor::new(image.clone())).unwrap();
+    assert_eq!(d64.tracks, 35);
+    assert_eq!(d64.data.len(), D64_35_TRACKS_SIZE);
+    assert_eq!(d64.sector_error(18, 0), Some(0x05));
+    assert_eq!(d64.sector_error(1, 0), Some(0x01));
+    assert_eq!(d64.sector_error(36, 0), None);
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+
+    let mut saved = Vec::new();
+    d64.to_writer(&mut saved).unwrap();
+    assert_eq!(saved, image);
+
+    assert_eq!(create_mock_d64().sector_error(18, 0), None);
+}
+
+#[test]
+fn test_insert_file_at() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file_at("PLACED", &[0x88; 300], 5, 7).unwrap();
+    let chain = d64.trace_file("PLACED").unwrap();
+    assert_eq!(chain, vec![(5, 7), (5, 17)]);
+    assert_eq!(d64.extract_file("PLACED").unwrap(), vec![0x88; 300]);
+
+    assert!(matches!(
+        d64.insert_file_at("AGAIN", b"x", 5, 7),
+        Err(D64Error::SectorInUse)
+    ));
+    assert!(matches!(
+        d64.insert_file_at("NOWHERE", b"x", 5, 30),
+        Err(D64Error::InvalidTrackSector)
+    ));
+    assert_eq!(d64.list_files().unwrap().len(), 2);
+}
+
+#[test]
+fn test_content_hash_and_diff() {
+    let original = create_mock_d64();
+    let mut modified = create_mock_d64();
+    assert_eq!(original.content_hash(), modified.content_hash());
+    assert!(original.diff(&modified).is_empty());
+
+    modified.write_sector(20, 4, &[0x99; 256]).unwrap();
+    assert_ne!(original.content_hash(), modified.content_hash());
+    assert_eq!(original.diff(&modified), vec![(20, 4)]);
+}
+
+#[test]
+fn test_entries_iterator() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SECOND", b"2").unwrap();
+    d64.insert_file("THIRD", b"3").unwrap();
+
+    let names: Vec<String> = d64
+        .entries()
+        .skip(1)
+        .take(1)
+        .map(|entry| entry.unwrap().name)
+        .collect();
+    assert_eq!(names, vec!["SECOND".to_string()]);
+    assert_eq!(
+        d64.entries()
+            .find(|entry| entry.as_ref().unwrap().name == "THIRD")
+            .unwrap()
+            .unwrap()
+            .file_type,
+        FileType::Prg
+    );
+
+    // Chain a second directory sector that links to itself.
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    dir[0] = 18;
+    dir[1] = 2;
+    d64.write_sector(18, 1, &dir).unwrap();
+    let mut next = d64.read_sector(18, 2).unwrap().to_vec();
+    next[0] = 18;
+    next[1] = 2;
+    d64.write_sector(18, 2, &next).unwrap();
+
+    let results: Vec<_> = d64.entries().collect();
+    assert_eq!(results.len(), 4);
+    assert!(matches!(results[3], Err(D64Error::InvalidTrackSector)));
+}
+
+#[test]
+fn test_geos_vlir_file() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("REC1", b"first record").unwrap();
+    d64.insert_file("REC2", b"second record").unwrap();
+    let rec1 = d64.find_file("REC1").unwrap();
+    let rec2 = d64.find_file("REC2").unwrap();
+
+    let mut index = vec![0u8; 256];
+    index[1] = 0xFF;
+    index[2..4].copy_from_slice(&[rec1.0, rec1.1]);
+    index[4..6].copy_from_slice(&[0x00, 0xFF]);
+    index[6..8].copy_from_slice(&[rec2.0, rec2.1]);
+    d64.write_sector(20, 0, &index).unwrap();
+
+    let mut entry = d64
+        .create_dir_entry("GEOS APP", FileType::Usr, 20, 0)
+        .unwrap();
+    entry[21] = 20;
+    entry[22] = 1;
+    entry[23] = 1;
+    entry[24] = 6;
+    let mut bam = d64.read_bam().unwrap();
+    d64.write_dir_entry(&mut bam, entry).unwrap();
+    d64.write_bam(&bam).unwrap();
+
+    let geos = d64
+        .list_entries()
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name == "GEOS APP")
+        .unwrap()
+        .geos
+        .unwrap();
+    assert!(geos.vlir);
+    assert_eq!(
+        (geos.info_track, geos.info_sector, geos.geos_type),
+        (20, 1, 6)
+    );
+    assert_eq!(d64.list_entries().unwrap()[0].geos, None);
+
+    assert_eq!(
+        d64.extract_geos("GEOS APP").unwrap(),
+        b"first recordsecond record"
+    );
+    assert_eq!(d64.extract_geos("REC1").unwrap(), b"first record");
+}
+
+#[test]
+fn test_write_sector_rejects_short_data() {
+    let mut d64 = create_mock_d64();
+    assert!(matches!(
+        d64.write_sector(1, 0, &[1, 2, 3]),
+        Err(D64Error::InvalidSectorLength(3))
+    ));
+}
+
+#[test]
+fn test_builder() {
+    let d64 = D64Builder::new()
+        .tracks(40)
+        .disk_name("BUILT")
+        .disk_id("01")
+        .add_file("ONE", b"first")
+        .add_file_typed("TWO", b"second", FileType::Seq)
+        .build()
+        .unwrap();
+
+    assert_eq!(d64.tracks, 40);
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_disk_name(), "BUILT");
+    assert_eq!(bam.get_disk_id(), "01");
+    assert_eq!(d64.list_files().unwrap(), vec!["ONE", "TWO"]);
+    assert_eq!(d64.list_entries().unwrap()[1].file_type, FileType::Seq);
+    assert_eq!(d64.extract_file("TWO").unwrap(), b"second");
+}
+
+#[test]
+fn test_builder_rejects_oversized_files() {
+    let result = D64Builder::new()
+        .add_file("BIG", &vec![0u8; 254 * 700])
+        .build();
+    assert!(matches!(result, Err(D64Error::DiskFull)));
+
+    let builder = (0..144).fold(D64Builder::new(), |builder, i| {
+        builder.add_file(&format!("FILE{i}"), b"x")
+    });
+    assert_eq!(
+        builder.clone().build().unwrap().list_files().unwrap().len(),
+        144
+    );
+    assert!(matches!(
+        builder.add_file("ONE TOO MANY", b"x").build(),
+        Err(D64Error::DiskFull)
+    ));
+}
+
+#[test]
+fn test_free_blocks() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("EMPTY", "01").unwrap();
+    let bam = d64.read_bam().unwrap();
+    let dir_free = bam.get_free_sectors_count(18).unwrap() as u16;
+    assert_eq!(
+        d64.free_blocks().unwrap(),
+        bam.total_free_sectors() - dir_free
+    );
+
+    let before = d64.free_blocks().unwrap();
+    d64.insert_file("TWO", &[0u8; 300]).unwrap();
+    assert_eq!(d64.free_blocks().unwrap(), before - 2);
+    assert!(d64
+        .format_directory()
+        .unwrap()
+        .ends_with(&format!("{} BLOCKS FREE.\n", before - 2)));
+}
+
+#[test]
+fn test_format_writes_dos_markers() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("MARKERS", "01").unwrap();
+    assert_eq!(&d64.read_sector(18, 0).unwrap()[165..167], b"2A");
+
+    let mut bam = d64.read_bam().unwrap();
+    assert_eq!(bam.dos_type(), 0x41);
+    assert_eq!(bam.get_format_id(), "2A");
+
+    bam.set_dos_type(0x42);
+    bam.set_format_id("2B");
+    d64.write_bam(&bam).unwrap();
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.dos_type(), 0x42);
+    assert_eq!(bam.get_format_id(), "2B");
+}
+
+#[test]
+fn test_sector_offset_table_matches_running_sum() {
+    for tracks in [35, 40, 70, 80] {
+        let d64 = D64::new(tracks).unwrap();
+        let mut expected = 0;
+        for track in 1..=tracks {
+            let sectors = sectors_per_track(tracks, track).unwrap();
+            for sector in 0..sectors {
+                assert_eq!(d64.sector_offset(track, sector).unwrap(), expected);
+                expected += 256;
+            }
+        }
+        assert_eq!(expected, d64.data.len());
+    }
+}
+
+#[test]
+fn test_read_rel() {
+    let mut d64 = create_mock_d64();
+    let records: Vec<u8> = (0..30u8).flat_map(|i| [i; 10]).collect();
+    d64.insert_file("RECORDS", &records).unwrap();
+    let (track, sector) = d64.find_file("RECORDS").unwrap();
+    let blocks = d64.trace_chain(track, sector).unwrap();
+    assert_eq!(blocks.len(), 2);
+
+    let mut side_sector = vec![0u8; 256];
+    side_sector[1] = 0x11;
+    side_sector[3] = 10;
+    side_sector[4..6].copy_from_slice(&[20, 0]);
+    for (i, (track, sector)) in blocks.iter().enumerate() {
+        side_sector[16 + i * 2] = *track;
+        side_sector[17 + i * 2] = *sector;
+    }
+    d64.write_sector(20, 0, &side_sector).unwrap();
+
+    let (dir_sector, offset) = d64.find_dir_entry("RECORDS").unwrap();
+    let mut dir = d64.read_sector(18, dir_sector).unwrap().to_vec();
+    dir[offset + 2] = 0x84;
+    dir[offset + 21] = 20;
+    dir[offset + 22] = 0;
+    dir[offset + 23] = 10;
+    d64.write_sector(18, dir_sector, &dir).unwrap();
+
+    let rel = d64.read_rel("RECORDS").unwrap();
+    assert_eq!(rel.record_length, 10);
+    assert_eq!(rel.record_count(), 30);
+    assert_eq!(rel.record(0), Some(&[0u8; 10][..]));
+    assert_eq!(rel.record(25), Some(&[25u8; 10][..]));
+    assert_eq!(rel.record(30), None);
+
+    assert!(matches!(
+        d64.read_rel("TEST FILE"),
+        Err(D64Error::NotRelFile)
+    ));
+}
+
+#[test]
+fn test_directory_grows_past_one_sector() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("MANY FILES", "01").unwrap();
+
+    let names: Vec<String> = (0..10).map(|i| format!("FILE{}", i)).collect();
+    for name in &names {
+        d64.insert_file(name, name.as_bytes()).unwrap();
+    }
+
+    assert_eq!(d64.list_files().unwrap(), names);
+    assert_eq!(d64.extract_file("FILE9").unwrap(), b"FILE9");
+    let first_dir = d64.read_sector(18, 1).unwrap();
+    assert_eq!(first_dir[0], 18);
+    assert!(!d64.read_bam().unwrap().is_sector_free(18, first_dir[1]));
+    assert!(d64.validate().unwrap().is_valid());
+}
+
+#[test]
+fn test_extract_all() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("A/B?", b"slashed").unwrap();
+    d64.insert_file("TEST FILE", b"duplicate").unwrap();
+
+    let out_dir = std::env::temp_dir().join(format!("dtools-extract-all-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&out_dir);
+    let extracted = d64.extract_all(&out_dir).unwrap();
+
+    let names: Vec<_> = extracted
+        .iter()
+        .map(|(name, path)| (name.as_str(), path.file_name().unwrap().to_str().unwrap()))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("TEST FILE", "TEST FILE.prg"),
+            ("A/B?", "A_B_.prg"),
+            ("TEST FILE", "TEST FILE_1.prg"),
+        ]
+    );
+    assert_eq!(fs::read(&extracted[2].1).unwrap(), b"duplicate");
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn test_insert_host_file() {
+    let mut d64 = create_mock_d64();
+    let path = std::env::temp_dir().join(format!("dtools-insert-{}.seq", std::process::id()));
+    fs::write(&path, b"from the host").unwrap();
+    d64.insert_host_file(&path, "NOTES", FileType::Seq).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let entry = &d64.list_entries().unwrap()[1];
+    assert_eq!(entry.name, "NOTES");
+    assert_eq!(entry.file_type, FileType::Seq);
+    assert_eq!(d64.extract_file("NOTES").unwrap(), b"from the host");
+    assert!(matches!(
+        d64.insert_host_file(&path, "GONE", FileType::Prg),
+        Err(D64Error::Io(_))
+    ));
+}
+
+#[test]
+fn test_extract_file_to_writer() {
+    let mut d64 = create_mock_d64();
+    let content: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    d64.insert_file("STREAM", &content).unwrap();
+
+    let mut out = Vec::new();
+    d64.extract_file_to("STREAM", &mut out).unwrap();
+    assert_eq!(out, content);
+    assert!(matches!(
+        d64.extract_file_to("MISSING", &mut out),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_disk_usage() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SIX HUNDRED", &[0xAB; 600]).unwrap();
+    assert_eq!(d64.file_block_count("SIX HUNDRED").unwrap(), 3);
+    assert_eq!(d64.file_block_count("TEST FILE").unwrap(), 1);
+
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[1];
+    slot[30..32].copy_from_slice(&3u16.to_le_bytes());
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+    let usage = &d64.disk_usage().unwrap()[1];
+    assert_eq!(usage.name, "SIX HUNDRED");
+    assert_eq!(usage.blocks, 3);
+    assert!(!usage.size_mismatch());
+
+    slot[30..32].copy_from_slice(&5u16.to_le_bytes());
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+    let usage = &d64.disk_usage().unwrap()[1];
+    assert_eq!(usage.stored_blocks, 5);
+    assert!(usage.size_mismatch());
+}
+
+#[test]
+fn test_move_entry() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("SECOND", b"two").unwrap();
+    d64.insert_file("THIRD", &[0x33; 600]).unwrap();
+    let chains: Vec<_> = ["TEST FILE", "SECOND", "THIRD"]
+        .iter()
+        .map(|name| d64.trace_file(name).unwrap())
+        .collect();
+
+    d64.move_entry(2, 0).unwrap();
+    assert_eq!(
+        d64.list_files().unwrap(),
+        vec!["THIRD", "TEST FILE", "SECOND"]
+    );
+    assert_eq!(d64.trace_file("THIRD").unwrap(), chains[2]);
+    assert_eq!(d64.trace_file("TEST FILE").unwrap(), chains[0]);
+    assert_eq!(d64.extract_file("THIRD").unwrap(), vec![0x33; 600]);
+    assert_eq!(d64.extract_file("SECOND").unwrap(), b"two");
+    assert_eq!(&d64.read_sector(18, 1).unwrap()[..2], &[0x00, 0xFF]);
+
+    d64.move_entry(0, 2).unwrap();
+    assert_eq!(
+        d64.list_files().unwrap(),
+        vec!["TEST FILE", "SECOND", "THIRD"]
+    );
+    assert!(matches!(d64.move_entry(3, 0), Err(D64Error::FileNotFound)));
+}
+
+#[test]
+fn test_find_files_with_wildcards() {
+    let mut d64 = create_mock_d64();
+    for name in ["PROGRAM", "PRG", "PRINTER", "DATA"] {
+        d64.insert_file(name, b"x").unwrap();
+    }
+
+    assert_eq!(
+        d64.find_files("PR*").unwrap(),
+        vec!["PROGRAM", "PRG", "PRINTER"]
+    );
+    assert_eq!(d64.find_files("P?G").unwrap(), vec!["PRG"]);
+    assert_eq!(d64.find_files("PR?G*").unwrap(), vec!["PROGRAM"]);
+    assert_eq!(d64.find_files("*").unwrap().len(), 5);
+    assert_eq!(d64.find_files("DATA").unwrap(), vec!["DATA"]);
+    assert!(d64.find_files("DAT").unwrap().is_empty());
+    assert!(d64.find_files("P?").unwrap().is_empty());
+}
+
+#[test]
+fn test_next_link() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("CHAIN", &[0x11; 600]).unwrap();
+    let chain = d64.trace_file("CHAIN").unwrap();
+    assert_eq!(chain.len(), 3);
+
+    assert_eq!(
+        d64.next_link(chain[0].0, chain[0].1).unwrap(),
+        Some(chain[1])
+    );
+    assert_eq!(
+        d64.next_link(chain[1].0, chain[1].1).unwrap(),
+        Some(chain[2])
+    );
+    assert_eq!(d64.next_link(chain[2].0, chain[2].1).unwrap(), None);
+
+    let mut broken = [0u8; 256];
+    broken[0] = 36;
+    d64.write_sector(1, 0, &broken).unwrap();
+    assert!(matches!(
+        d64.next_link(1, 0),
+        Err(D64Error::InvalidTrackSector)
+    ));
+    assert!(matches!(
+        d64.next_link(1, 21),
+        Err(D64Error::InvalidTrackSector)
+    ));
+}
+
+#[test]
+fn test_create_empty_file() {
+    let mut d64 = create_mock_d64();
+    let free_before = d64.free_blocks().unwrap();
+    d64.create_empty_file("LOG", FileType::Seq).unwrap();
+
+    let entry = &d64.list_entries().unwrap()[1];
+    assert_eq!(entry.file_type, FileType::Seq);
+    assert_eq!(d64.free_blocks().unwrap(), free_before - 1);
+    let data = d64
+        .read_sector(entry.start_track, entry.start_sector)
+        .unwrap();
+    assert_eq!(&data[..2], &[0, 1]);
+    assert!(d64.extract_file("LOG").unwrap().is_empty());
+
+    d64.append_file("LOG", &[0x42; 300]).unwrap();
+    assert_eq!(d64.extract_file("LOG").unwrap(), vec![0x42; 300]);
+    assert_eq!(d64.trace_file("LOG").unwrap().len(), 2);
+}
+
+#[test]
+fn test_locked_file_cannot_be_deleted() {
+    let mut d64 = create_mock_d64();
+    d64.set_locked("TEST FILE", true).unwrap();
+    assert!(d64.list_entries().unwrap()[0].locked);
+    assert!(d64.format_directory().unwrap().contains(" PRG<\n"));
+    assert!(matches!(
+        d64.delete_file("TEST FILE"),
+        Err(D64Error::FileLocked)
+    ));
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+
+    d64.set_locked("TEST FILE", false).unwrap();
+    assert!(!d64.list_entries().unwrap()[0].locked);
+    d64.set_locked("TEST FILE", true).unwrap();
+    d64.delete_file_with("TEST FILE", true).unwrap();
+    assert!(d64.list_files().unwrap().is_empty());
+}
+
+#[test]
+fn test_insert_sets_block_count() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("THREE", &[0x33; 600]).unwrap();
+    d64.create_empty_file("EMPTY", FileType::Seq).unwrap();
+
+    let entries = d64.list_entries().unwrap();
+    assert_eq!(entries[0].size_blocks, 1);
+    assert_eq!(entries[1].size_blocks, 3);
+    assert_eq!(entries[2].size_blocks, 1);
+    assert!(d64
+        .disk_usage()
+        .unwrap()
+        .iter()
+        .all(|usage| !usage.size_mismatch()));
+    assert!(d64.format_directory().unwrap().contains("3    \"THREE\""));
+
+    d64.append_file("THREE", &[0x44; 300]).unwrap();
+    assert_eq!(d64.list_entries().unwrap()[1].size_blocks, 4);
+}
+
+#[test]
+fn test_extract_file_by_index() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("TEST FILE", b"duplicate").unwrap();
+
+    let entries = d64.list_entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+    assert_eq!(d64.extract_file_by_index(0).unwrap(), b"Hello, World!");
+    assert_eq!(d64.extract_file_by_index(1).unwrap(), b"duplicate");
+    assert_eq!(
+        d64.trace_file_by_index(1).unwrap()[0],
+        (entries[1].start_track, entries[1].start_sector)
+    );
+    assert!(matches!(
+        d64.extract_file_by_index(2),
+        Err(D64Error::FileNotFound)
+    ));
+    assert!(matches!(
+        d64.trace_file_by_index(2),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_with_bam_batches_allocations() {
+    let mut d64 = create_mock_d64();
+    let free_before = d64.free_blocks().unwrap();
+    let bam_before = d64.read_sector(18, 0).unwrap().to_vec();
+
+    let result: Result<(), D64Error> = d64.with_bam(|bam| {
+        for sector in 0..21 {
+            bam.allocate_sector(1, sector)?;
+        }
+        Err(D64Error::DiskFull)
+    });
+    assert!(matches!(result, Err(D64Error::DiskFull)));
+    assert_eq!(d64.read_sector(18, 0).unwrap(), bam_before.as_slice());
+
+    let allocated = d64
+        .with_bam(|bam| {
+            let mut allocated = Vec::new();
+            for _ in 0..100 {
+                let (track, sector) = bam.first_free_sector(false)?;
+                bam.allocate_sector(track, sector)?;
+                allocated.push((track, sector));
+            }
+            Ok(allocated)
+        })
+        .unwrap();
+    assert_eq!(allocated.len(), 100);
+    assert_eq!(d64.free_blocks().unwrap(), free_before - 100);
+    assert!(allocated
+        .iter()
+        .all(|&(track, sector)| !d64.read_bam().unwrap().is_sector_free(track, sector)));
+
+    take_sector_writes();
+    d64.insert_file("LARGE", &[0x55; 254 * 200]).unwrap();
+    let writes = take_sector_writes();
+    assert_eq!(writes.len(), 200 + 2);
+    assert_eq!(writes.iter().filter(|&&block| block == (18, 0)).count(), 1);
+
+    d64.append_file("LARGE", &[0x66; 254 * 50]).unwrap();
+    let writes = take_sector_writes();
+    assert_eq!(writes.iter().filter(|&&block| block == (18, 0)).count(), 1);
+}
+
+#[test]
+fn test_list_scratched() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("KEEP", b"keep").unwrap();
+    let start = d64.trace_file("TEST FILE").unwrap()[0];
+    assert!(d64.list_scratched().unwrap().is_empty());
+
+    d64.delete_file("TEST FILE").unwrap();
+    let scratched = d64.list_scratched().unwrap();
+    assert_eq!(
+        scratched,
+        vec![ScratchedEntry {
+            name: "TEST FILE".to_string(),
+            start_track: start.0,
+            start_sector: start.1,
+        }]
+    );
+}
+
+#[test]
+fn test_custom_dir_track() {
+    assert_eq!(D64::new(35).unwrap().dir_track, 18);
+    assert_eq!(D64::new(70).unwrap().dir_track, 18);
+    assert_eq!(D64::new(80).unwrap().dir_track, 40);
+
+    let mut d64 = create_mock_d64();
+    let mut header = d64.read_sector(18, 0).unwrap().to_vec();
+    let mut dir = d64.read_sector(18, 1).unwrap().to_vec();
+    header[0] = 20;
+    dir[0] = 0;
+    d64.write_sector(20, 0, &header).unwrap();
+    d64.write_sector(20, 1, &dir).unwrap();
+    d64.write_sector(18, 1, &[0; 256]).unwrap();
+
+    d64.dir_track = 20;
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+    assert_eq!(d64.read_bam().unwrap().get_disk_name(), "TEST DISK");
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+
+    d64.rename_file("TEST FILE", "MOVED").unwrap();
+    assert_eq!(entry_name(&d64.read_sector(20, 1).unwrap()[5..21]), "MOVED");
+}
+
+#[test]
+fn test_format_with_custom_dir_track() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.dir_track = 20;
+    d64.format("MOVED DIR", "2A").unwrap();
+
+    let bam = d64.read_bam().unwrap();
+    assert!(bam.is_allocated(20, 0).unwrap());
+    assert!(bam.is_allocated(20, 1).unwrap());
+    assert!(!bam.is_allocated(18, 0).unwrap());
+
+    let content: Vec<u8> = (0..254 * 80).map(|i| (i % 251) as u8).collect();
+    d64.insert_file("BIG", &content).unwrap();
+    assert!(d64
+        .trace_file("BIG")
+        .unwrap()
+        .iter()
+        .all(|&(track, _)| track != 20));
+    assert_eq!(d64.list_files().unwrap(), vec!["BIG"]);
+    assert_eq!(d64.extract_file("BIG").unwrap(), content);
+}
+
+#[test]
+fn test_error_block_survives_sector_writes() {
+    let mut bytes = create_mock_d64().into_bytes();
+    let error_block: Vec<u8> = (0..683).map(|i| (i % 11) as u8).collect();
+    bytes.extend(&error_block);
+    let mut d64 = D64::from_bytes(bytes).unwrap();
+    assert_eq!(d64.data.len(), 174848);
+
+    d64.write_sector(35, 16, &[0xFF; 256]).unwrap();
+    d64.write_track(1, &[0xEE; 21 * 256]).unwrap();
+    assert_eq!(d64.data.len(), 174848);
+
+    let mut saved = Vec::new();
+    d64.to_writer(&mut saved).unwrap();
+    assert_eq!(saved.len(), 175531);
+    assert_eq!(&saved[174848..], error_block.as_slice());
+    assert_eq!(&saved[174848 - 256..174848], &[0xFF; 256]);
+
+    d64.data.push(0);
+    assert!(matches!(
+        d64.to_writer(Vec::new()),
+        Err(D64Error::InvalidFileSize)
+    ));
+}
+
+#[test]
+fn test_from_file_lenient_ignores_trailing_bytes() {
+    let d64 = create_mock_d64();
+    let mut bytes = d64.clone().into_bytes();
+    bytes.extend([0xEE; 100]);
+    assert_eq!(bytes.len(), 174848 + 100);
+    assert!(matches!(
+        D64::from_bytes(bytes.clone()),
+        Err(D64Error::InvalidFileSize)
+    ));
+
+    let path = std::env::temp_dir().join(format!("dtools-lenient-{}.d64", std::process::id()));
+    fs::write(&path, &bytes).unwrap();
+    let loaded = D64::from_file_lenient(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, d64);
+    assert!(loaded.error_info.is_none());
+
+    assert!(matches!(
+        D64::from_bytes_lenient(vec![0; 174848 - 1]),
+        Err(D64Error::InvalidFileSize)
+    ));
+
+    let mut long_footer = d64.clone().into_bytes();
+    long_footer.extend([0xEE; 30000]);
+    let loaded = D64::from_bytes_lenient(long_footer).unwrap();
+    assert_eq!(loaded.tracks, 35);
+    assert!(loaded.error_info.is_none());
+    assert_eq!(loaded, d64);
+
+    let mut error_sized = d64.clone().into_bytes();
+    error_sized.extend([0xEE; 700]);
+    assert!(D64::from_bytes_lenient(error_sized)
+        .unwrap()
+        .error_info
+        .is_none());
+
+    let extended = D64::new_formatted(40, "EXTENDED", "40").unwrap();
+    let mut bytes = extended.clone().into_bytes();
+    bytes.extend([0xEE; 3000]);
+    assert_eq!(D64::from_bytes_lenient(bytes).unwrap(), extended);
+}
+
+#[test]
+fn test_save_to_file_verified() {
+    let mut d64 = D64::new_formatted(35, "VERIFIED", "01").unwrap();
+    d64.error_info = Some(vec![1; 683]);
+    let path = std::env::temp_dir().join(format!("dtools-verified-{}.d64", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    d64.save_to_file_verified(path).unwrap();
+    let loaded = D64::from_file(path).unwrap();
+    assert_eq!(loaded.content_hash(), d64.content_hash());
+    assert_eq!(loaded.error_info, d64.error_info);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_clone_image() {
+    let d64 = create_mock_d64();
+    let mut copy = d64.clone_image();
+    assert_eq!(copy.content_hash(), d64.content_hash());
+
+    copy.insert_file("ONLY COPY", b"copy").unwrap();
+    assert_ne!(copy.content_hash(), d64.content_hash());
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+
+    let path = std::env::temp_dir().join(format!("dtools-clone-{}.d64", std::process::id()));
+    let path = path.to_str().unwrap();
+    d64.save_to_file(path).unwrap();
+    let read_only = D64::open_read_only(path).unwrap();
+    assert!(!read_only.clone_image().is_read_only());
+
+    let target = format!("{}.copy", path);
+    read_only.clone_to_file(&target).unwrap();
+    let loaded = D64::from_file(&target).unwrap();
+    assert_eq!(loaded.content_hash(), d64.content_hash());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(&target).unwrap();
+}
+
+#[test]
+fn test_load_address() {
+    let mut d64 = create_mock_d64();
+    d64.import_prg("LOADER", 0xC000, &[0x60]).unwrap();
+    d64.insert_file("TINY", b"X").unwrap();
+
+    assert_eq!(d64.load_address("LOADER").unwrap(), Some(0xC000));
+    assert_eq!(d64.load_address("TEST FILE").unwrap(), Some(0x6548));
+    assert_eq!(d64.load_address("TINY").unwrap(), None);
+    assert!(matches!(
+        d64.load_address("MISSING"),
+        Err(D64Error::FileNotFound)
+    ));
+}
+
+#[test]
+fn test_compact() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("FIRST", &[0x11; 3000]).unwrap();
+    d64.insert_file("GAP", &[0x22; 5000]).unwrap();
+    d64.insert_file("LAST", &[0x33; 2000]).unwrap();
+    d64.delete_file("GAP").unwrap();
+    d64.insert_file_with_policy("SCATTER", &[0x44; 4000], InterleavePolicy::Custom(7))
+        .unwrap();
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[2];
+    slot[2] |= 0x40;
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+    let free_before = d64.free_blocks().unwrap();
+
+    d64.compact().unwrap();
+
+    let names = d64.list_files().unwrap();
+    assert_eq!(names, vec!["TEST FILE", "FIRST", "SCATTER", "LAST"]);
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+    assert_eq!(d64.extract_file("FIRST").unwrap(), vec![0x11; 3000]);
+    assert_eq!(d64.extract_file("LAST").unwrap(), vec![0x33; 2000]);
+    assert_eq!(d64.extract_file("SCATTER").unwrap(), vec![0x44; 4000]);
+    assert_eq!(d64.directory_slots().unwrap()[2].3[2], 0xC2);
+    assert_eq!(d64.free_blocks().unwrap(), free_before);
+
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_disk_name(), "TEST DISK");
+    assert_eq!(bam.get_disk_id(), "2A");
+
+    // The result matches writing the same files to a fresh disk in order.
+    let mut fresh = D64::new(35).unwrap();
+    fresh.format("TEST DISK", "2A").unwrap();
+    for name in &names {
+        fresh
+            .insert_file(name, &d64.extract_file(name).unwrap())
+            .unwrap();
+    }
+    assert_eq!(
+        fresh.read_bam().unwrap().bitmap,
+        d64.read_bam().unwrap().bitmap
+    );
+    assert!(d64.validate().unwrap().is_valid());
+}
+
+#[test]
+fn test_compact_keeps_del_entries() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file("GAP", &[0x22; 3000]).unwrap();
+    d64.insert_file("----------------", &[0x00; 10]).unwrap();
+    d64.insert_file("LAST", &[0x33; 2000]).unwrap();
+    d64.delete_file("GAP").unwrap();
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[2];
+    slot[2] = 0x80;
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+    let live_names = |d64: &D64| -> Vec<String> {
+        d64.directory_slots()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, _, slot)| slot[2] != 0)
+            .map(|(_, _, _, slot)| entry_name(&slot[5..21]))
+            .collect()
+    };
+    assert_eq!(live_names(&d64), ["TEST FILE", "----------------", "LAST"]);
+
+    d64.compact().unwrap();
+
+    assert_eq!(live_names(&d64), ["TEST FILE", "----------------", "LAST"]);
+    let del = d64.directory_slots().unwrap()[1].3;
+    assert_eq!(del[2], 0x80);
+    assert_eq!(d64.read_chain(del[3], del[4]).unwrap(), vec![0x00; 10]);
+    assert!(!d64.read_bam().unwrap().is_sector_free(del[3], del[4]));
+    assert_eq!(d64.extract_file("LAST").unwrap(), vec![0x33; 2000]);
+}
+
+#[test]
+fn test_set_load_address() {
+    let mut d64 = create_mock_d64();
+    d64.import_prg("LOADER", 0x0801, &[0xA9, 0x00, 0x60])
+        .unwrap();
+    d64.set_load_address("LOADER", 0xC000).unwrap();
+
+    assert_eq!(d64.load_address("LOADER").unwrap(), Some(0xC000));
+    assert_eq!(
+        d64.extract_prg("LOADER").unwrap(),
+        (0xC000, vec![0xA9, 0x00, 0x60])
+    );
+
+    d64.insert_file("TINY", b"X").unwrap();
+    assert!(matches!(
+        d64.set_load_address("TINY", 0xC000),
+        Err(D64Error::FileTooShort)
+    ));
+    assert_eq!(d64.extract_file("TINY").unwrap(), b"X");
+}
+
+#[test]
+fn test_rel_entry_parameters() {
+    let mut d64 = create_mock_d64();
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[1];
+    slot[2] = 0x84;
+    slot[3] = 17;
+    slot[4] = 5;
+    slot[5..21].copy_from_slice(&[0xA0; 16]);
+    slot[5..10].copy_from_slice(b"RECS1");
+    slot[21] = 17;
+    slot[22] = 6;
+    slot[23] = 120;
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+
+    let entries = d64.list_entries().unwrap();
+    let rel = &entries[1];
+    assert_eq!(rel.file_type, FileType::Rel);
+    assert_eq!(rel.record_length, Some(120));
+    assert_eq!(rel.side_sector_track, Some(17));
+    assert_eq!(rel.side_sector_sector, Some(6));
+    assert!(rel.geos.is_none());
+
+    let prg = &entries[0];
+    assert_eq!(prg.record_length, None);
+    assert_eq!(prg.side_sector_track, None);
+    assert_eq!(prg.side_sector_sector, None);
+}
+
+#[test]
+fn test_splat_file_reported_as_open() {
+    let mut d64 = create_mock_d64();
+    let (dir_sector, offset) = d64.find_dir_entry("TEST FILE").unwrap();
+    let mut dir = d64.read_sector(18, dir_sector).unwrap().to_vec();
+    dir[offset + 2] = 0x02;
+    d64.write_sector(18, dir_sector, &dir).unwrap();
+
+    let entry = &d64.list_entries().unwrap()[0];
+    assert_eq!(entry.file_type, FileType::Prg);
+    assert!(!entry.is_closed);
+    assert!(d64
+        .format_directory()
+        .unwrap()
+        .contains("\"TEST FILE\"       *PRG"));
+}
+
+#[test]
+fn test_geometry_queries() {
+    for (tracks, total) in [(35, 683), (40, 768), (70, 1366), (80, 3200)] {
+        let d64 = D64::new(tracks).unwrap();
+        assert_eq!(d64.total_sectors(), total);
+        assert_eq!(d64.total_sectors() * 256, d64.data.len());
+        assert!(matches!(
+            d64.sectors_in_track(tracks + 1),
+            Err(D64Error::InvalidTrackSector)
+        ));
+    }
+
+    let d64 = D64::new(40).unwrap();
+    assert_eq!(d64.sectors_in_track(1).unwrap(), 21);
+    assert_eq!(d64.sectors_in_track(18).unwrap(), 19);
+    assert_eq!(d64.sectors_in_track(40).unwrap(), 17);
+    assert_eq!(D64::new(70).unwrap().sectors_in_track(36).unwrap(), 21);
+    assert_eq!(D64::new(80).unwrap().sectors_in_track(1).unwrap(), 40);
+    assert!(d64.sectors_in_track(0).is_err());
+}
+
+#[test]
+fn test_all_sectors() {
+    for tracks in [35, 40, 70, 80] {
+        let d64 = D64::new(tracks).unwrap();
+        let sectors: Vec<_> = d64.all_sectors().collect();
+        assert_eq!(sectors.len(), d64.total_sectors());
+        assert_eq!(sectors.len() * 256, d64.data.len());
+        assert!(sectors
+            .iter()
+            .all(|&(track, sector)| d64.read_sector(track, sector).is_ok()));
+    }
+
+    let d64 = D64::new(35).unwrap();
+    let sectors: Vec<_> = d64.all_sectors().collect();
+    assert_eq!(sectors[0], (1, 0));
+    assert_eq!(sectors[21], (2, 0));
+    assert_eq!(sectors[sectors.len() - 1], (35, 16));
+}
+
+#[test]
+fn test_free_block_list() {
+    for tracks in [35, 40, 70, 80] {
+        let d64 = D64::new_formatted(tracks, "FREE", "01").unwrap();
+        let bam = d64.read_bam().unwrap();
+        let free = d64.free_blocks_list().unwrap();
+        assert_eq!(free.len(), bam.total_free_sectors() as usize);
+        assert!(free
+            .iter()
+            .all(|&(track, sector)| bam.is_sector_free(track, sector)));
+    }
+
+    let mut d64 = create_mock_d64();
+    let free = d64.free_blocks_list().unwrap();
+    assert_eq!(free[0], (17, 1));
+    assert_eq!(free.last(), Some(&(18, 18)));
+
+    d64.allocate_sector(17, 1).unwrap();
+    assert_eq!(d64.free_blocks_list().unwrap()[0], (17, 2));
+}
+
+#[test]
+fn test_block_map() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("MAP", "01").unwrap();
+    let map = d64.block_map().unwrap();
+
+    assert_eq!(map.len(), 35);
+    assert_eq!(map[0].len(), 21);
+    assert_eq!(map[34].len(), 17);
+    assert!(map[17][0]);
+    assert!(map[17][1]);
+    assert!(map[0].iter().all(|&allocated| !allocated));
+}
+
+#[test]
+fn test_format_40_track_bam_extension() {
+    for (layout, offset) in [(BamLayout::SpeedDos, 0xC0), (BamLayout::DolphinDos, 0xAC)] {
+        let mut d64 = D64::new(40).unwrap();
+        d64.format_with_layout("FORTY TRACKS", "40", layout)
+            .unwrap();
+        assert_eq!(d64.read_sector(18, 0).unwrap()[offset], 17);
+
+        let bam = d64.read_bam().unwrap();
+        assert_eq!(bam.layout, layout);
+        assert_eq!(bam.get_disk_name(), "FORTY TRACKS");
+        for track in 36..=40 {
+            assert_eq!(bam.get_free_sectors_count(track).unwrap(), 17);
+        }
+    }
+}
+
+#[test]
+fn test_extended_42_track_images() {
+    for (tracks, size) in [(41, 200960), (42, 205312)] {
+        let mut d64 = D64::new_formatted(tracks, "OVERFORMAT", "42").unwrap();
+        assert_eq!(d64.data.len(), size);
+        assert_eq!(d64.total_sectors() * 256, size);
+        assert_eq!(d64.sectors_in_track(tracks).unwrap(), 17);
+        assert!(d64.sectors_in_track(tracks + 1).is_err());
+
+        d64.write_sector(tracks, 16, &[0x42; 256]).unwrap();
+        d64.allocate_sector(tracks, 16).unwrap();
+        let bam = d64.read_bam().unwrap();
+        assert_eq!(bam.get_free_sectors_count(tracks).unwrap(), 16);
+        assert_eq!(bam.get_disk_name(), "OVERFORMAT");
+
+        let mut bytes = d64.clone().into_bytes();
+        assert_eq!(D64::from_bytes(bytes.clone()).unwrap(), d64);
+        bytes.extend(vec![1; d64.total_sectors()]);
+        let loaded = D64::from_bytes(bytes).unwrap();
+        assert_eq!(loaded.tracks, tracks);
+        assert_eq!(loaded.sector_error(tracks, 16), Some(1));
+        assert_eq!(loaded.read_sector(tracks, 16).unwrap(), &[0x42; 256]);
+    }
+}
+
+#[test]
+fn test_40_track_bam_keeps_disk_name() {
+    let mut d64 = D64::new(40).unwrap();
+    d64.format("FORTY TRACKS", "40").unwrap();
+    d64.allocate_sector(40, 16).unwrap();
+
+    let raw = d64.read_sector(18, 0).unwrap();
+    assert_eq!(raw[0xD0], 16);
+    assert_eq!(&raw[0xD1..0xD4], &[0xFF, 0xFF, 0x00]);
+    assert_eq!(&raw[144..156], b"FORTY TRACKS");
+
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_free_sectors_count(40).unwrap(), 16);
+    assert_eq!(bam.get_free_sectors_count(35).unwrap(), 17);
+    assert!(!bam.is_sector_free(40, 16));
+    assert_eq!(bam.get_disk_name(), "FORTY TRACKS");
+}
+
+#[test]
+fn test_bam_consistency() {
+    for tracks in [35, 40, 70, 80] {
+        let d64 = D64::new_formatted(tracks, "CHECK", "01").unwrap();
+        assert!(d64.read_bam().unwrap().is_consistent());
+    }
+
+    let mut bam = create_mock_d64().read_bam().unwrap();
+    assert!(bam.is_consistent());
+    bam.free_sectors[0] = 3;
+    assert!(!bam.is_consistent());
+    bam.recount_free();
+    assert!(bam.is_consistent());
+    assert_eq!(bam.get_free_sectors_count(1).unwrap(), 21);
+
+    bam.bitmap[0][0] = 0xF0;
+    assert!(!bam.is_consistent());
+    bam.recount_free();
+    assert_eq!(bam.get_free_sectors_count(1).unwrap(), 17);
+}
+
+#[test]
+fn test_is_allocated() {
+    let mut d64 = create_mock_d64();
+    d64.allocate_sector(5, 7).unwrap();
+
+    assert!(d64.is_allocated(5, 7).unwrap());
+    assert!(!d64.is_allocated(5, 8).unwrap());
+    assert!(d64.is_allocated(18, 0).unwrap());
+    assert!(matches!(
+        d64.is_allocated(5, 21),
+        Err(D64Error::InvalidTrackSector)
+    ));
+
+    d64.free_sector(5, 7).unwrap();
+    assert!(!d64.read_bam().unwrap().is_allocated(5, 7).unwrap());
+}
+
+#[test]
+fn test_raw_disk_name_bytes() {
+    let bam = create_mock_d64().read_bam().unwrap();
+    let mut expected = [0xA0; 16];
+    expected[..9].copy_from_slice(b"TEST DISK");
+    assert_eq!(bam.disk_name_bytes(), &expected);
+    assert_eq!(bam.disk_id_bytes(), b"2A");
+    assert_eq!(bam.disk_name_trimmed(), b"TEST DISK");
+    assert_eq!(bam.get_disk_name(), "TEST DISK");
+
+    let mut bam = bam;
+    bam.disk_name = *b"SIXTEEN CHARS!!!";
+    assert_eq!(bam.disk_name_trimmed(), b"SIXTEEN CHARS!!!");
+    bam.disk_name[4] = 0xA0;
+    assert_eq!(bam.disk_name_trimmed(), b"SIXT");
+    assert_eq!(bam.disk_name_bytes()[5..], *b"EN CHARS!!!");
+}
+
+#[test]
+fn test_bam_from_truncated_sector() {
+    assert!(matches!(
+        BAM::from_sector_data(&[0; 10], 35),
+        Err(D64Error::InvalidSectorLength(10))
+    ));
+}
+
+#[test]
+fn test_quick_format_keeps_data() {
+    let mut d64 = create_mock_d64();
+    let (track, sector) = d64.trace_file("TEST FILE").unwrap()[0];
+    let file_sector = d64.read_sector(track, sector).unwrap().to_vec();
+    d64.write_sector(18, 5, &[0x55; 256]).unwrap();
+
+    d64.quick_format("QUICK", "QF").unwrap();
+
+    assert_eq!(
+        d64.read_sector(track, sector).unwrap(),
+        file_sector.as_slice()
+    );
+    assert_eq!(d64.read_sector(18, 5).unwrap(), &[0x55; 256]);
+    assert!(d64.list_files().unwrap().is_empty());
+    assert_eq!(d64.free_blocks().unwrap(), 664);
+    let bam = d64.read_bam().unwrap();
+    assert_eq!(bam.get_disk_name(), "QUICK");
+    assert_eq!(bam.get_disk_id(), "QF");
+
+    let mut d81 = D64::new_formatted(80, "D81", "81").unwrap();
+    d81.write_sector(1, 0, &[0x81; 256]).unwrap();
+    d81.quick_format("AGAIN", "01").unwrap();
+    assert_eq!(d81.read_sector(1, 0).unwrap(), &[0x81; 256]);
+    assert_eq!(d81.read_bam().unwrap().get_disk_name(), "AGAIN");
+}
+
+#[test]
+fn test_format_reserves_only_bam_and_directory() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("FRESH", "01").unwrap();
+    let bam = d64.read_bam().unwrap();
+
+    assert_eq!(bam.get_free_sectors_count(19).unwrap(), 19);
+    assert_eq!(bam.get_free_sectors_count(18).unwrap(), 17);
+    assert!(!bam.is_sector_free(18, 0));
+    assert!(!bam.is_sector_free(18, 1));
+    assert!(bam.is_sector_free(18, 2));
+    assert_eq!(
+        &d64.read_sector(18, 0).unwrap()[4..8],
+        &[21, 0xFF, 0xFF, 0x1F]
+    );
+    assert!(d64.validate().unwrap().is_valid());
+}
+
+#[test]
+fn test_boot_info() {
+    let mut d64 = create_mock_d64();
+    let info = d64.boot_info().unwrap();
+    assert!(!info.c128_boot);
+    assert!(!info.likely_autostart);
+
+    let mut boot = vec![0u8; 256];
+    boot[..3].copy_from_slice(b"CBM");
+    boot[7..12].copy_from_slice(b"GAMES");
+    d64.write_sector(1, 0, &boot).unwrap();
+    let info = d64.boot_info().unwrap();
+    assert!(info.c128_boot);
+    assert!(info.likely_autostart);
+    assert_eq!(info.message.as_deref(), Some("GAMES"));
+    assert_eq!(&info.first_bytes[..3], b"CBM");
+}
+
+#[test]
+fn test_insert_too_large_file_consumes_nothing() {
+    let mut d64 = create_mock_d64();
+    let free_before = d64.free_blocks().unwrap();
+    let too_big = vec![0u8; (free_before as usize + 1) * 254];
+
+    assert!(!d64.can_fit(too_big.len()).unwrap());
+    assert!(d64.can_fit(free_before as usize * 254).unwrap());
+    assert!(matches!(
+        d64.insert_file("TOO BIG", &too_big),
+        Err(D64Error::DiskFull)
+    ));
+    assert_eq!(d64.free_blocks().unwrap(), free_before);
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE"]);
+}
+
+#[test]
+fn test_failed_insert_leaves_image_unchanged() {
+    let mut d64 = create_mock_d64();
+    let filler = vec![0xAAu8; (d64.free_blocks().unwrap() as usize - 3) * 254];
+    d64.insert_file("FILLER", &filler).unwrap();
+    assert_eq!(d64.free_blocks().unwrap(), 3);
+
+    let hash_before = d64.content_hash();
+    assert!(matches!(
+        d64.insert_file("OVERFLOW", &[0u8; 254 * 4]),
+        Err(D64Error::DiskFull)
+    ));
+    assert!(matches!(
+        d64.insert_file("NAME MUCH TOO LONG", b"x"),
+        Err(D64Error::NameTooLong)
+    ));
+    assert_eq!(d64.content_hash(), hash_before);
+    assert_eq!(d64.free_blocks().unwrap(), 3);
+    assert_eq!(d64.list_files().unwrap(), vec!["TEST FILE", "FILLER"]);
+
+    d64.insert_file("FITS", &[0u8; 254 * 3]).unwrap();
+    assert_eq!(d64.free_blocks().unwrap(), 0);
+}
+
+#[test]
+fn test_append_file() {
+    let mut d64 = create_mock_d64();
+    d64.insert_file_typed("LOG", b"first line\n", FileType::Seq)
+        .unwrap();
+
+    d64.append_file("LOG", b"second line\n").unwrap();
+    assert_eq!(
+        d64.extract_file("LOG").unwrap(),
+        b"first line\nsecond line\n"
+    );
+    assert_eq!(d64.trace_file("LOG").unwrap().len(), 1);
+
+    let extra: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let free_before = d64.free_blocks().unwrap();
+    d64.append_file("LOG", &extra).unwrap();
+
+    let mut expected = b"first line\nsecond line\n".to_vec();
+    expected.extend(&extra);
+    assert_eq!(d64.extract_file("LOG").unwrap(), expected);
+    assert_eq!(d64.free_blocks().unwrap(), free_before - 2);
+
+    let entry = d64
+        .list_entries()
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name == "LOG")
+        .unwrap();
+    assert_eq!(entry.size_blocks, 3);
+    assert!(d64.validate().unwrap().is_valid());
+}
+
+#[test]
+fn test_track_round_trip() {
+    let source = create_mock_d64();
+    let track = source.read_track(17).unwrap();
+    assert_eq!(track.len(), 21 * 256);
+    assert_eq!(&track[..256], source.read_sector(17, 0).unwrap());
+
+    let mut target = D64::new(35).unwrap();
+    target.write_track(17, &track).unwrap();
+    assert_eq!(target.read_track(17).unwrap(), track);
+    assert!(matches!(
+        target.write_track(18, &track),
+        Err(D64Error::InvalidTrackLength(5376))
+    ));
+    assert!(target.read_track(36).is_err());
+}
+
+#[test]
+fn test_copy_sectors() {
+    let source = create_mock_d64();
+    let mut target = D64::new(40).unwrap();
+    let span = SectorSpan {
+        from: (17, 20),
+        to: (36, 0),
+        count: 3,
+    };
+    copy_sectors(&source, &mut target, &[span]).unwrap();
+
+    assert_eq!(
+        target.read_sector(36, 0).unwrap(),
+        source.read_sector(17, 20).unwrap()
+    );
+    assert_eq!(
+        target.read_sector(36, 1).unwrap(),
+        source.read_sector(18, 0).unwrap()
+    );
+    assert_eq!(
+        target.read_sector(36, 2).unwrap(),
+        source.read_sector(18, 1).unwrap()
+    );
+
+    let mut untouched = D64::new(35).unwrap();
+    let overrun = SectorSpan {
+        from: (1, 0),
+        to: (35, 16),
+        count: 2,
+    };
+    assert!(matches!(
+        copy_sectors(&source, &mut untouched, &[overrun]),
+        Err(D64Error::InvalidTrackSector)
+    ));
+    assert!(untouched.data.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_read_only_image_rejects_writes() {
+    let path = std::env::temp_dir().join(format!("dtools-read-only-{}.d64", std::process::id()));
+    let path = path.to_str().unwrap();
+    create_mock_d64().save_to_file(path).unwrap();
+
+    let mut d64 = D64::open_read_only(path).unwrap();
+    let hash_before = d64.content_hash();
+    assert!(d64.is_read_only());
+    assert!(matches!(
+        d64.write_sector(1, 0, &[0u8; 256]),
+        Err(D64Error::ReadOnly)
+    ));
+    assert!(matches!(
+        d64.insert_file("NEW", b"data"),
+        Err(D64Error::ReadOnly)
+    ));
+    assert!(matches!(d64.allocate_sector(1, 0), Err(D64Error::ReadOnly)));
+    assert!(matches!(d64.save_to_file(path), Err(D64Error::ReadOnly)));
+    assert_eq!(d64.content_hash(), hash_before);
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_directory_slots() {
+    let d64 = create_mock_d64();
+    let slots = d64.directory_slots().unwrap();
+    assert_eq!(slots.len(), 8);
+
+    let (track, sector, offset, slot) = slots[0];
+    assert_eq!((track, sector, offset), (18, 1, 0));
+    assert_eq!(slot[2], 0x82);
+    assert_eq!(entry_name(&slot[5..21]), "TEST FILE");
+    assert_eq!(slots[1].2, 32);
+    assert_eq!(slots[1].3[2], 0);
+}
+
+#[test]
+fn test_write_dir_slot() {
+    let mut d64 = create_mock_d64();
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[0];
+    slot[0..2].copy_from_slice(&[0x12, 0x34]);
+    slot[5..14].copy_from_slice(b"EDITED   ");
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+
+    assert_eq!(d64.list_files().unwrap(), vec!["EDITED   "]);
+    assert_eq!(&d64.read_sector(18, 1).unwrap()[..2], &[0x00, 0xFF]);
+
+    let (_, _, offset, mut empty) = d64.directory_slots().unwrap()[3];
+    empty.copy_from_slice(&d64.create_dir_entry("RAW", FileType::Seq, 17, 0).unwrap());
+    d64.write_dir_slot(18, 1, offset, &empty).unwrap();
+    assert_eq!(d64.list_files().unwrap(), vec!["EDITED   ", "RAW"]);
+
+    for (track, sector, offset) in [(18, 1, 33), (18, 1, 256), (17, 0, 0), (18, 2, 0)] {
+        assert!(matches!(
+            d64.write_dir_slot(track, sector, offset, &slot),
+            Err(D64Error::InvalidTrackSector)
+        ));
+    }
+}
+
+#[test]
+fn test_sanitize_filename() {
+    assert_eq!(sanitize_filename("A/B", FileType::Prg), "A_B.prg");
+    assert_eq!(
+        sanitize_filename("LOG\\2\u{a0}  ", FileType::Seq),
+        "LOG_2.seq"
+    );
+    assert_eq!(sanitize_filename("\u{7}..", FileType::Usr), "_.usr");
+    assert_eq!(sanitize_filename("", FileType::Rel), "_.rel");
+}
+
+#[test]
+fn test_clone_and_compare_images() {
+    let original = create_mock_d64();
+    let mut copy = original.clone();
+    assert_eq!(copy, original);
+    assert_eq!(copy.read_bam().unwrap(), original.read_bam().unwrap());
+
+    copy.insert_file("EXTRA", b"more").unwrap();
+    assert_ne!(copy, original);
+    assert_ne!(copy.read_bam().unwrap(), original.read_bam().unwrap());
+    assert_eq!(original.list_files().unwrap(), vec!["TEST FILE"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let d64 = create_mock_d64();
+    let bam = d64.read_bam().unwrap();
+    let json = serde_json::to_value(&bam).unwrap();
+    assert_eq!(json["disk_name"], "TEST DISK");
+    assert_eq!(json["disk_id"], "2A");
+    assert_eq!(json["bitmap"][0], "FFFF1F0000");
+    assert_eq!(serde_json::from_value::<BAM>(json).unwrap(), bam);
+
+    let entries = d64.list_entries().unwrap();
+    let json = serde_json::to_string(&entries).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<DirEntry>>(&json).unwrap(),
+        entries
+    );
+}
+
+#[test]
+fn test_from_bytes_round_trip() {
+    let bytes = create_mock_d64().into_bytes();
+    assert_eq!(bytes.len(), 174848);
+
+    let d64 = D64::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(d64.tracks, 35);
+    assert_eq!(d64.extract_file("TEST FILE").unwrap(), b"Hello, World!");
+    assert_eq!(d64.into_bytes(), bytes);
+
+    let mut with_errors = D64::new(40).unwrap().into_bytes();
+    with_errors.extend(vec![1u8; 768]);
+    let d64 = D64::from_bytes(with_errors.clone()).unwrap();
+    assert_eq!(d64.tracks, 40);
+    assert_eq!(d64.into_bytes(), with_errors);
+
+    assert!(matches!(
+        D64::from_bytes(vec![0u8; 1000]),
+        Err(D64Error::InvalidFileSize)
+    ));
+}
+
+#[test]
+fn test_sector_data_checksum() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.write_sector(1, 0, &[0x01; 256]).unwrap();
+    assert_eq!(d64.sector_data_checksum(1, 0).unwrap(), 0x00);
+
+    let mut data = [0x01; 256];
+    data[0] = 0x03;
+    d64.write_sector(1, 1, &data).unwrap();
+    assert_eq!(d64.sector_data_checksum(1, 1).unwrap(), 0x02);
+    assert!(d64.verify_against_error_info().unwrap().is_empty());
+
+    let mut error_info = vec![0x01; 683];
+    error_info[1] = 0x05;
+    d64.error_info = Some(error_info);
+    assert_eq!(d64.verify_against_error_info().unwrap(), vec![(1, 1, 0x02)]);
+}
+
+#[test]
+fn test_reserve_and_release_directory() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("RESERVE", "01").unwrap();
+    let mut bam = d64.read_bam().unwrap();
+
+    bam.release_directory().unwrap();
+    assert_eq!(bam.get_free_sectors_count(18).unwrap(), 19);
+    assert!(bam.is_sector_free(18, 0));
+
+    bam.reserve_directory().unwrap();
+    assert_eq!(bam.get_free_sectors_count(18).unwrap(), 17);
+    assert!(!bam.is_sector_free(18, 0));
+    assert!(!bam.is_sector_free(18, 1));
+
+    bam.reserve_directory().unwrap();
+    assert_eq!(bam.get_free_sectors_count(18).unwrap(), 17);
+}
+
+#[test]
+fn test_find_free_sector() {
+    let d64 = create_mock_d64();
+    let (track, sector) = d64.find_free_sector().unwrap();
+    assert!(track > 0 && track <= 35);
+    assert!(sector < SECTORS_PER_TRACK[(track - 1) as usize]);
+}
+
+#[test]
+fn test_allocate_next() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("CHAIN", "01").unwrap();
+
+    assert_eq!(d64.allocate_next(17, None).unwrap(), (17, 0));
+    assert_eq!(d64.allocate_next(17, Some(0)).unwrap(), (17, 10));
+    assert_eq!(d64.allocate_next(17, Some(10)).unwrap(), (17, 20));
+    assert!(!d64.read_bam().unwrap().is_sector_free(17, 10));
+
+    for _ in 3..21 {
+        d64.allocate_next(17, None).unwrap();
+    }
+    assert_eq!(d64.allocate_next(17, Some(20)).unwrap(), (19, 0));
+    assert!(matches!(
+        d64.allocate_next(36, None),
+        Err(D64Error::InvalidTrackSector)
+    ));
+}
+
+#[test]
+fn test_find_free_sector_skips_directory_track() {
+    let mut d64 = D64::new(35).unwrap();
+    d64.format("FULL", "01").unwrap();
+    let mut bam = d64.read_bam().unwrap();
+    for track in (1..=35).filter(|&track| track != 18) {
+        for sector in 0..SECTORS_PER_TRACK[(track - 1) as usize] {
+            bam.allocate_sector(track, sector).unwrap();
+        }
+    }
+    d64.write_bam(&bam).unwrap();
+
+    assert!(matches!(d64.find_free_sector(), Err(D64Error::DiskFull)));
+    assert_eq!(d64.find_free_sector_with(true).unwrap(), (18, 2));
+
+    assert_eq!(&allocation_order(35, 18, false)[..4], &[17, 19, 16, 20]);
+    assert!(!allocation_order(35, 18, false).contains(&18));
+}
+
+#[test]
+fn test_trace_file() {
+    let d64 = create_mock_d64();
+    let sectors = d64.trace_file("TEST FILE").unwrap();
+    assert!(!sectors.is_empty());
+    assert_eq!(sectors[0].0, 17); // First sector should be just below the directory track
+}
+
+#[test]
+#[should_panic(expected = "InvalidTrackSector")]
+fn test_invalid_sector_access() {
+    let d64 = create_mock_d64();
+    d64.read_sector(0, 0).unwrap(); // Track 0 doesn't exist
+}
+
+#[test]
+fn test_petscii_conversion() {
+    let ascii = "HELLO, WORLD!";
+    let petscii = ascii_to_petscii(ascii);
+    let back_to_ascii = petscii_to_ascii(&petscii);
+    assert_eq!(ascii, back_to_ascii);
+}
+
+#[test]
+fn test_screencode_conversion() {
+    assert_eq!(
+        ascii_to_screencode("@AZ [] 09?"),
+        vec![0x00, 0x01, 0x1A, 0x20, 0x1B, 0x1D, 0x20, 0x30, 0x39, 0x3F]
+    );
+    assert_eq!(ascii_to_screencode("hello"), ascii_to_screencode("HELLO"));
+    assert_eq!(
+        screencode_to_ascii(&ascii_to_screencode("HELLO, WORLD!")),
+        "HELLO, WORLD!"
+    );
+
+    let reversed = ascii_to_screencode_with("HI", true);
+    assert_eq!(reversed, vec![0x88, 0x89]);
+    assert_eq!(screencode_to_ascii(&reversed), "HI");
+    assert_eq!(screencode_to_ascii(&[0x40, 0x7F]), "??");
+}
+
+#[test]
+fn test_cbm_error_codes() {
+    let cases = [
+        (
+            D64Error::Io(std::io::ErrorKind::NotFound.into()),
+            74,
+            "DRIVE NOT READY",
+        ),
+        (D64Error::InvalidFileSize, 74, "DRIVE NOT READY"),
+        (D64Error::InvalidTrackSector, 66, "ILLEGAL TRACK AND SECTOR"),
+        (D64Error::FileNotFound, 62, "FILE NOT FOUND"),
+        (D64Error::DiskFull, 72, "DISK FULL"),
+        (D64Error::NameTooLong, 33, "SYNTAX ERROR"),
+        (D64Error::UnrepresentableChar('\u{e9}'), 33, "SYNTAX ERROR"),
+        (D64Error::InvalidSectorLength(10), 30, "SYNTAX ERROR"),
+        (D64Error::InvalidTrackLength(10), 30, "SYNTAX ERROR"),
+        (D64Error::InvalidArchive, 64, "FILE TYPE MISMATCH"),
+        (D64Error::NotRelFile, 64, "FILE TYPE MISMATCH"),
+        (D64Error::InvalidBasic, 64, "FILE TYPE MISMATCH"),
+        (D64Error::CannotRelocate, 64, "FILE TYPE MISMATCH"),
+        (
+            D64Error::UnsupportedFileType(FileType::Rel),
+            64,
+            "FILE TYPE MISMATCH",
+        ),
+        (D64Error::FileTooShort, 50, "RECORD NOT PRESENT"),
+        (D64Error::SectorInUse, 65, "NO BLOCK"),
+        (D64Error::ReadOnly, 26, "WRITE PROTECT ON"),
+        (D64Error::FileLocked, 26, "WRITE PROTECT ON"),
+        (D64Error::InvalidGcr, 24, "READ ERROR"),
+        (D64Error::VerifyFailed, 25, "WRITE ERROR"),
+    ];
+    for (error, code, message) in cases {
+        assert_eq!(error.to_cbm_error(), (code, message.to_string()));
+    }
+
+    let (code, message) = D64Error::FileNotFound.to_cbm_error();
+    assert_eq!(
+        format!("{},{},00,00", code, message),
+        "62,FILE NOT FOUND,00,00"
+    );
+}
+
+#[test]
+fn test_strict_petscii_encoding() {
+    assert_eq!(
+        try_ascii_to_petscii("Hi!").unwrap(),
+        ascii_to_petscii("Hi!")
+    );
+    assert!(matches!(
+        try_ascii_to_petscii("DISK \u{1F4BE}"),
+        Err(D64Error::UnrepresentableChar('\u{1F4BE}'))
+    ));
+
+    let mut bam = create_mock_d64().read_bam().unwrap();
+    assert!(matches!(
+        bam.set_disk_name("CAF\u{c9}"),
+        Err(D64Error::UnrepresentableChar('\u{c9}'))
+    ));
+    assert!(bam.set_disk_id("{}").is_err());
+    assert_eq!(bam.get_disk_name(), "TEST DISK");
+    assert_eq!(bam.get_disk_id(), "2A");
+}
+
+#[test]
+fn test_format_rejects_unrepresentable_names() {
+    let mut d64 = create_mock_d64();
+    let before = d64.clone();
+    assert!(matches!(
+        d64.format("CAF\u{c9}", "2A"),
+        Err(D64Error::UnrepresentableChar('\u{c9}'))
+    ));
+    assert!(matches!(
+        d64.quick_format("DISK", "\u{e9}1"),
+        Err(D64Error::UnrepresentableChar('\u{e9}'))
+    ));
+    assert_eq!(d64, before);
+
+    let mut d81 = D64::new(80).unwrap();
+    assert!(matches!(
+        d81.format("1581 \u{1F4BE}", "81"),
+        Err(D64Error::UnrepresentableChar('\u{1F4BE}'))
+    ));
+
+    assert!(matches!(
+        D64::new_formatted(35, "NAME", "\u{c9}D"),
+        Err(D64Error::UnrepresentableChar('\u{c9}'))
+    ));
+    assert!(matches!(
+        D64Builder::new().disk_name("B\u{fc}ILT").build(),
+        Err(D64Error::UnrepresentableChar('\u{fc}'))
+    ));
+}
+
+#[test]
+fn test_petscii_to_display() {
+    assert_eq!(
+        petscii_to_display(&[0x93, 0x05, b'H', b'I', 0x12, b'!', 0x92]),
+        "{CLR}{WHT}HI{RVS ON}!{RVS OFF}"
+    );
+    assert_eq!(petscii_to_display(&[0xC1, 0x9D, 0x01]), "A{LEFT}{$01}");
+    assert_eq!(petscii_to_ascii(&[0x93, 0x05, b'H']), "??H");
+
+    let mut d64 = create_mock_d64();
+    let (track, sector, offset, mut slot) = d64.directory_slots().unwrap()[0];
+    slot[5] = 0x12;
+    d64.write_dir_slot(track, sector, offset, &slot).unwrap();
+    let entry = &d64.list_entries().unwrap()[0];
+    assert_eq!(entry.display_name(), "{RVS ON}EST FILE");
+    assert_eq!(entry.name, "?EST FILE");
+}
+
+#[test]
+fn test_petscii_unicode_tables() {
+    assert_eq!(petscii_to_unicode(&[0xC1], CharSet::Uppercase), "♠");
+    assert_eq!(petscii_to_unicode(&[0xC1], CharSet::Lowercase), "A");
+    assert_eq!(petscii_to_unicode(&[0x41], CharSet::Lowercase), "a");
+    assert_eq!(
+        petscii_to_unicode(&[0x5C, 0xA0, 0xDE], CharSet::Uppercase),
+        "£\u{a0}π"
+    );
+    assert_eq!(petscii_to_unicode(&[0x61], CharSet::Uppercase), "♠");
+
+    for charset in [CharSet::Uppercase, CharSet::Lowercase] {
+        for byte in (0x00..=0x5F).chain(0x80..=0xDF) {
+            let text = petscii_to_unicode(&[byte], charset);
+            assert_eq!(unicode_to_petscii(&text, charset), vec![byte]);
+        }
+    }
+}
+
+#[test]
+fn test_lowercase_charset_conversion() {
+    let petscii = ascii_to_petscii_with("Hello World", CharSet::Lowercase);
+    assert_eq!(petscii[0], 0xC8);
+    assert_eq!(petscii[1], 0x45);
+    assert_eq!(
+        petscii_to_ascii_with(&petscii, CharSet::Lowercase),
+        "Hello World"
+    );
+    assert_eq!(petscii_to_ascii(&petscii), "HELLO WORLD");
+}
+
+fn create_mock_t64() -> Vec<u8> {
+    let mut image = vec![0u8; 64 + 2 * 32];
+    image[..19].copy_from_slice(b"C64 tape image file");
+    image[0x20] = 0x01;
+    image[0x21] = 0x01;
+    image[0x22] = 2;
+    image[0x24] = 1;
+    image[0x28..0x2C].copy_from_slice(b"TAPE");
+
+    let record = &mut image[64..96];
+    record[0] = 1;
+    record[1] = 0x82;
+    record[2..4].copy_from_slice(&0x0801u16.to_le_bytes());
+    // Bogus end address, as written by many T64 tools.
+    record[4..6].copy_from_slice(&0xC3C6u16.to_le_bytes());
+    record[8..12].copy_from_slice(&128u32.to_le_bytes());
+    record[16..32].fill(0x20);
+    record[16..20].copy_from_slice(b"GAME");
+
+    image.extend_from_slice(&[0x0B, 0x08, 0x0A, 0x00, 0x9E, 0x32, 0x30, 0x36, 0x31]);
+    image
+}
+
+#[test]
+fn test_read_t64() {
+    let entries = t64::read_t64(Cursor::new(create_mock_t64())).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "GAME");
+    assert_eq!(entries[0].load_address, 0x0801);
+    assert_eq!(entries[0].data.len(), 9);
+
+    let mut d64 = create_mock_d64();
+    d64.import_t64_entry(&entries[0]).unwrap();
+    let content = d64.extract_file("GAME").unwrap();
+    assert_eq!(&content[..2], &[0x01, 0x08]);
+    assert_eq!(&content[2..], &entries[0].data[..]);
+
+    assert!(matches!(
+        t64::read_t64(Cursor::new(vec![0u8; 64])),
+        Err(D64Error::InvalidArchive)
+    ));
+}
+
+fn create_mock_lynx() -> Vec<u8> {
+    let mut image = vec![
+        0x01, 0x08, 0x0B, 0x08, 0x0A, 0x00, 0x9E, 0x32, 0x30, 0x36, 0x31,
+    ];
+    image.extend_from_slice(&[0x00, 0x00, 0x00, 0x0D]);
+    image.extend_from_slice(b" 1  *LYNX XII  BY WILL CORLEY\r 2 \r");
+    image.extend_from_slice(b"HELLO\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\r 1 \rP\r 11 \r");
+    image
+        .extend_from_slice(b"DATA\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\xA0\r 2 \rS\r 47 \r");
+    image.resize(254, 0x00);
+
+    let mut first = b"0123456789".to_vec();
+    first.resize(254, 0x00);
+    image.extend_from_slice(&first);
+    image.extend((0..300).map(|i| i as u8));
+    image
+}
+
+#[test]
+fn test_read_lynx() {
+    let entries = lynx::read_lynx(Cursor::new(create_mock_lynx())).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "HELLO");
+    assert_eq!(entries[0].file_type, FileType::Prg);
+    assert_eq!(entries[0].data, b"0123456789");
+    assert_eq!(entries[1].name, "DATA");
+    assert_eq!(entries[1].file_type, FileType::Seq);
+    assert_eq!(entries[1].data.len(), 300);
+    assert_eq!(entries[1].data[299], (299 % 256) as u8);
+
+    let mut d64 = create_mock_d64();
+    d64.import_lynx_entry(&entries[1]).unwrap();
+    assert_eq!(d64.extract_file("DATA").unwrap(), entries[1].data);
+
+    assert!(matches!(
+        lynx::read_lynx(Cursor::new(vec![0u8; 64])),
+        Err(D64Error::InvalidArchive)
+    ));
+}
+
+fn create_mock_basic() -> Vec<u8> {
+    vec![
+        0x12, 0x08, 0x0A, 0x00, 0x99, 0x20, 0x22, 0x41, 0x4E, 0x44, 0x93, 0x22, 0x3B, 0x31, 0xAF,
+        0x31, 0x00, 0x1B, 0x08, 0x14, 0x00, 0x89, 0x20, 0x31, 0x30, 0x00, 0x00, 0x00,
+    ]
+}
+
+#[test]
+fn test_detokenize_basic() {
+    let listing = basic::detokenize(0x0801, &create_mock_basic()).unwrap();
+    assert_eq!(listing, "10 PRINT \"AND{$93}\";1AND1\n20 GOTO 10\n");
+
+    assert!(matches!(
+        basic::detokenize(0x0801, &[0x13, 0x08, 0x0A]),
+        Err(D64Error::InvalidBasic)
+    ));
+}
+
+#[test]
+fn test_tokenize_basic() {
+    let source = "10 PRINT \"AND{$93}\";1AND1\n20 GOTO 10\n";
+    let program = basic::tokenize(source, 0x0801).unwrap();
+    assert_eq!(program, create_mock_basic());
+    assert_eq!(basic::detokenize(0x0801, &program).unwrap(), source);
+
+    let program = basic::tokenize("10 REM PRINT GOTO\n20 DATA TO,STOP:PRINT", 0x0801).unwrap();
+    assert_eq!(
+        &program[4..16],
+        &[0x8F, b' ', b'P', b'R', b'I', b'N', b'T', b' ', b'G', b'O', b'T', b'O']
+    );
+    assert_eq!(
+        &program[21..],
+        &[0x83, b' ', b'T', b'O', b',', b'S', b'T', b'O', b'P', b':', 0x99, 0, 0, 0]
+    );
+
+    let source = "10 REM {$05}GRAPHICS{$DE}:{$1C}\n";
+    let program = basic::tokenize(source, 0x0801).unwrap();
+    assert_eq!(&program[4..9], &[0x8F, b' ', 0x05, b'G', b'R']);
+    assert_eq!(basic::detokenize(0x0801, &program).unwrap(), source);
+
+    assert!(matches!(
+        basic::tokenize("PRINT", 0x0801),
+        Err(D64Error::InvalidBasic)
+    ));
+    assert!(matches!(
+        basic::tokenize("10 REM CAF\u{e9}", 0x0801),
+        Err(D64Error::InvalidBasic)
+    ));
+}
+
+#[test]
+fn test_p00_round_trip() {
+    let mut p00 = Vec::new();
+    pc64::write_p00(&mut p00, "GAME", &[0x01, 0x08, 0x60]).unwrap();
+    assert_eq!(&p00[..8], b"C64File\0");
+    assert_eq!(p00.len(), 26 + 3);
+
+    let (name, record_size, data) = pc64::read_p00(Cursor::new(&p00)).unwrap();
+    assert_eq!(name, "GAME");
+    assert_eq!(record_size, 0);
+    assert_eq!(data, vec![0x01, 0x08, 0x60]);
+
+    let mut d64 = create_mock_d64();
+    d64.import_p00(Cursor::new(&p00), FileType::Prg).unwrap();
+    assert_eq!(d64.extract_file("GAME").unwrap(), data);
+
+    assert!(matches!(
+        pc64::read_p00(Cursor::new(vec![0u8; 40])),
+        Err(D64Error::InvalidArchive)
+    ));
+}
+
+#[test]
+fn test_gcr_round_trip() {
+    assert_eq!(
+        gcr::encode_gcr(&[0x00, 0x00, 0x00, 0x00]),
+        vec![0x52, 0x94, 0xA5, 0x29, 0x4A]
+    );
+    assert_eq!(gcr::encode_gcr(&[0x08, 0x00, 0x00, 0x00]).len(), 5);
+
+    let data: Vec<u8> = (0..=255).collect();
+    let encoded = gcr::encode_gcr(&data);
+    assert_eq!(encoded.len(), 320);
+    assert_eq!(gcr::decode_gcr(&encoded).unwrap(), data);
+
+    for nibble in 0..16u8 {
+        assert_eq!(
+            gcr::GCR_DECODE[gcr::GCR_ENCODE[nibble as usize] as usize],
+            Some(nibble)
+        );
+    }
+    assert!(matches!(
+        gcr::decode_gcr(&[0x00; 5]),
+        Err(D64Error::InvalidGcr)
+    ));
+    assert!(matches!(
+        gcr::decode_gcr(&[0x52; 4]),
+        Err(D64Error::InvalidGcr)
+    ));
+}